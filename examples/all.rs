@@ -103,3 +103,6 @@
 //   );
 //   Box::new(at404)
 // }
+
+// kept for reference, example above is written against the old server api
+fn main() {}
@@ -1,8 +1,6 @@
 use tokio::net::TcpListener;
 use tokio::prelude::*;
 
-use peta;
-
 fn main() {
   let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
   let addr = "127.0.0.1:3000".parse().unwrap();
@@ -12,12 +10,23 @@ fn main() {
 
   let mut router = peta::router::Router::new();
 
-  router.add("GET", "/", |(mut req, res)| {
-    Box::new(res.write("Index".as_bytes()).map(|res| ((req, res))))
+  router.add("GET", "/", |(req, res)| {
+    Box::new(res.write("Index".as_bytes()).map(|res| (req, res)))
+  });
+
+  router.add("GET", "/hello", |(req, res)| {
+    Box::new(res.write("Hello world".as_bytes()).map(|res| (req, res)))
   });
 
-  router.add("GET", "/hello", |(mut req, res)| {
-    Box::new(res.write("Hello world".as_bytes()).map(|res| ((req, res))))
+  router.add("GET", "/stream", |(req, res)| {
+    Box::new(
+      res
+        .write_head()
+        .and_then(|res| res.write_chunk("Hello "))
+        .and_then(|res| res.write_chunk("world"))
+        .and_then(|res| res.finish())
+        .map(|res| (req, res)),
+    )
   });
 
   // router.get("/hello", |(mut req, res)| {
@@ -60,11 +69,10 @@ fn main() {
       // sock.set_keepalive(Some(std::time::Duration::from_secs(1)));
       // sock
       // sock.set_timeout(Duration::from_secs(5));
-      let reader = peta::reader::Reader::new(sock.split(), &router)
+      let reader = peta::reader::Reader::new(sock, &router)
         .map_err(|e| eprintln!("Error {}", e))
         .map(|_| {
           println!("Socket closed");
-          ()
         });
 
       tokio::runtime::current_thread::spawn(reader);
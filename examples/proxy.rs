@@ -0,0 +1,38 @@
+use tokio::net::TcpListener;
+use tokio::prelude::*;
+
+// server behind HAProxy (or any balancer with `send-proxy` / `send-proxy-v2`),
+// every connection starts with PROXY header carrying the real client address
+fn main() {
+  let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+  let addr = "127.0.0.1:3000".parse().unwrap();
+  let listener = TcpListener::bind(&addr).expect("unable to bind TCP listener");
+
+  let mut router = peta::router::Router::new();
+
+  router.add("GET", "/", |(req, res)| {
+    let body = match req.peer_addr() {
+      Some(addr) => format!("Hello {}", addr),
+      None => "Hello".to_string(),
+    };
+
+    Box::new(res.write(body).map(|res| (req, res)))
+  });
+
+  let server = listener
+    .incoming()
+    .map_err(|e| eprintln!("accept failed = {:?}", e))
+    .for_each(move |sock| {
+      // header has to arrive within header timeout like the tls handshake
+      let reader = peta::reader::Reader::accept(peta::proxy::accept(sock), &router)
+        .map_err(|e| eprintln!("Error {}", e))
+        .map(|_| ());
+
+      tokio::runtime::current_thread::spawn(reader);
+
+      Ok(())
+    });
+
+  runtime.spawn(server);
+  runtime.run().unwrap();
+}
@@ -0,0 +1,38 @@
+use tokio::net::UnixListener;
+use tokio::prelude::*;
+
+fn main() {
+  let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+  let path = "/tmp/peta.sock";
+
+  // remove socket file left from previous run
+  let _ = std::fs::remove_file(path);
+  let listener = UnixListener::bind(path).expect("unable to bind unix listener");
+
+  let mut router = peta::router::Router::new();
+
+  router.add("GET", "/", |(req, res)| {
+    let body = match req.peer_cred() {
+      Some(cred) => format!("Hello uid {}", cred.uid),
+      None => "Hello".to_string(),
+    };
+
+    Box::new(res.write(body).map(|res| (req, res)))
+  });
+
+  let server = listener
+    .incoming()
+    .map_err(|e| eprintln!("accept failed = {:?}", e))
+    .for_each(move |sock| {
+      let reader = peta::reader::Reader::new(sock, &router)
+        .map_err(|e| eprintln!("Error {}", e))
+        .map(|_| ());
+
+      tokio::runtime::current_thread::spawn(reader);
+
+      Ok(())
+    });
+
+  runtime.spawn(server);
+  runtime.run().unwrap();
+}
@@ -1,110 +1,301 @@
 use super::*;
 
+// chunk size is limited to 64 bits
+const MAX_SIZE_DIGITS: usize = 16;
+const MAX_EXT_LEN: usize = 1024;
+const MAX_TRAILERS_LEN: usize = 8192;
+
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+pub type Trailers = hashbrown::HashMap<String, Vec<u8>>;
+
 pub enum ParseStatus {
   // is_last: bool, data: BytesMut
   Chunk(bool, BytesMut),
   NotEnoughData,
 }
 
-pub fn parse(buffer: &mut BytesMut) -> Result<ParseStatus, std::io::Error> {
-  let mut error_counter = 16;
-  let mut pos = 1; // we start with 1 as we do one additional pos move at the "\r" part
-  let mut size = 0;
-  let mut is_last = false;
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+  Size,
+  SizeWs,
+  Ext,
+  SizeLf,
+  Data,
+  DataCr,
+  DataLf,
+  Trailers,
+  Done,
+}
 
-  let mut bytes_iter = buffer.iter();
+// resumable chunked body decoder, keeps its position between reads
+// so chunk lines can be split across any number of tcp packets
+pub struct Decoder {
+  state: State,
+  size: u64,
+  digits: usize,
+  ext_len: usize,
+}
 
-  let mut in_chunk_size = true;
-  let mut in_ext = false;
+impl Decoder {
+  pub fn new() -> Decoder {
+    Decoder {
+      state: State::Size,
+      size: 0,
+      digits: 0,
+      ext_len: 0,
+    }
+  }
 
-  loop {
-    let byte = bytes_iter
-      .next()
-      .expect("Could not unwrap bytes_iter.next()");
-    pos += 1;
+  pub fn reset(&mut self) {
+    self.state = State::Size;
+    self.size = 0;
+    self.digits = 0;
+    self.ext_len = 0;
+  }
 
-    if error_counter == 0 {
-      return Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "Invalid chunk",
-      ));
-    }
+  pub fn decode(
+    &mut self,
+    buffer: &mut BytesMut,
+    trailers: &mut Trailers,
+  ) -> Result<ParseStatus, std::io::Error> {
+    let mut data: Option<BytesMut> = None;
 
-    match byte {
-      b'0'...b'9' if in_chunk_size => {
-        error_counter -= 1;
-        size *= 16;
-        size += (byte - b'0') as usize;
-      }
-      b'a'...b'f' if in_chunk_size => {
-        error_counter -= 1;
-        size *= 16;
-        size += (byte + 10 - b'a') as usize;
-      }
-      b'A'...b'F' if in_chunk_size => {
-        error_counter -= 1;
-        size *= 16;
-        size += (byte + 10 - b'A') as usize;
-      }
+    loop {
+      match self.state {
+        State::Done => return Ok(ParseStatus::Chunk(true, data.unwrap_or_default())),
+        State::Data => {
+          // emit collected data before starting next chunk to avoid copies
+          if buffer.is_empty() || data.is_some() {
+            break;
+          }
 
-      b'\r' => {
-        let byte = bytes_iter
-          .next()
-          .expect("Could not unwrap bytes_iter.next()");
+          let amt = std::cmp::min(self.size, buffer.len() as u64) as usize;
+          self.size -= amt as u64;
+          data = Some(buffer.split_to(amt));
 
-        match byte {
-          b'\n' => {
-            if size == 0 {
-              is_last = true;
-              break;
+          if self.size == 0 {
+            self.state = State::DataCr;
+          }
+        }
+        State::Trailers => {
+          let mut headers = [httparse::EMPTY_HEADER; 50];
+          match httparse::parse_headers(buffer, &mut headers) {
+            Ok(httparse::Status::Complete((amt, headers))) => {
+              for header in headers.iter() {
+                trailers.insert(header.name.to_lowercase(), header.value.to_vec());
+              }
+
+              buffer.advance(amt);
+              self.state = State::Done;
             }
-            if buffer.len() - pos - size == 7 && bytes_iter.nth(size + 2).unwrap() == &b'0' {
-              is_last = true;
+            Ok(httparse::Status::Partial) => {
+              if buffer.len() > MAX_TRAILERS_LEN {
+                return Err(error("Chunk trailers are too long"));
+              }
+              break;
             }
-            break;
+            Err(_e) => return Err(error("Invalid chunk trailers")),
           }
-          _ => {
-            return Err(std::io::Error::new(
-              std::io::ErrorKind::Other,
-              "Invalid chunk",
-            ));
+        }
+        state => {
+          if buffer.is_empty() {
+            break;
           }
-        };
+
+          let byte = buffer[0];
+          buffer.advance(1);
+          self.state = self.next(state, byte)?;
+        }
       }
-      b';' if !in_ext => {
-        in_ext = true;
-        in_chunk_size = false;
+    }
+
+    match data {
+      Some(data) => Ok(ParseStatus::Chunk(false, data)),
+      None => Ok(ParseStatus::NotEnoughData),
+    }
+  }
+
+  // handle single byte of chunk framing
+  fn next(&mut self, state: State, byte: u8) -> Result<State, std::io::Error> {
+    match (state, byte) {
+      (State::Size, b'0'..=b'9') => self.push_digit(byte - b'0'),
+      (State::Size, b'a'..=b'f') => self.push_digit(byte + 10 - b'a'),
+      (State::Size, b'A'..=b'F') => self.push_digit(byte + 10 - b'A'),
+      (State::Size, _) if self.digits == 0 => Err(error("Invalid chunk size")),
+      (State::Size, b'\t') | (State::Size, b' ') => Ok(State::SizeWs),
+      (State::SizeWs, b'\t') | (State::SizeWs, b' ') => Ok(State::SizeWs),
+      (State::Size, b';') | (State::SizeWs, b';') => Ok(State::Ext),
+      (State::Size, b'\r') | (State::SizeWs, b'\r') | (State::Ext, b'\r') => Ok(State::SizeLf),
+      (State::Ext, b'\n') => Err(error("Invalid chunk extension")),
+      (State::Ext, _) => {
+        self.ext_len += 1;
+        if self.ext_len > MAX_EXT_LEN {
+          return Err(error("Chunk extension is too long"));
+        }
+        Ok(State::Ext)
       }
-      b'\t' | b' ' if !in_ext & !in_chunk_size => {}
-      b'\t' | b' ' if in_chunk_size => in_chunk_size = false,
-      _ if in_ext => {}
-      _ => {
-        return Err(std::io::Error::new(
-          std::io::ErrorKind::Other,
-          "Invalid chunk",
-        ));
+      (State::SizeLf, b'\n') => {
+        self.digits = 0;
+        self.ext_len = 0;
+        if self.size == 0 {
+          Ok(State::Trailers)
+        } else {
+          Ok(State::Data)
+        }
       }
+      (State::DataCr, b'\r') => Ok(State::DataLf),
+      (State::DataLf, b'\n') => Ok(State::Size),
+      _ => Err(error("Invalid chunk")),
+    }
+  }
+
+  fn push_digit(&mut self, value: u8) -> Result<State, std::io::Error> {
+    self.digits += 1;
+    if self.digits > MAX_SIZE_DIGITS {
+      return Err(error("Chunk size is too long"));
     }
+
+    self.size = self.size * 16 + u64::from(value);
+    Ok(State::Size)
   }
+}
 
-  if buffer.len() < size + pos + 2 {
-    return Ok(ParseStatus::NotEnoughData);
+impl Default for Decoder {
+  fn default() -> Decoder {
+    Decoder::new()
   }
+}
 
-  buffer.advance(pos);
-  if is_last {
-    if size == 0 {
-      buffer.advance(2);
-      return Ok(ParseStatus::Chunk(true, buffer.split_to(size)));
+fn error(message: &'static str) -> std::io::Error {
+  std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BODY: &[u8] =
+    b"5;name=value\r\nhello\r\n6\r\n world\r\n0\r\nexpires: never\r\nx-sum: 1\r\n\r\n";
+
+  // feed parts one by one, collecting data until the last chunk
+  fn decode(parts: &[&[u8]]) -> Result<(Vec<u8>, Trailers, BytesMut), std::io::Error> {
+    let mut decoder = Decoder::new();
+    let mut trailers = Trailers::new();
+    let mut buffer = BytesMut::new();
+    let mut body = Vec::new();
+    let mut parts = parts.iter();
+
+    loop {
+      match decoder.decode(&mut buffer, &mut trailers)? {
+        ParseStatus::Chunk(is_last, data) => {
+          body.extend_from_slice(&data);
+          if is_last {
+            return Ok((body, trailers, buffer));
+          }
+        }
+        ParseStatus::NotEnoughData => match parts.next() {
+          Some(part) => buffer.extend_from_slice(part),
+          None => panic!("decoder needs more data than the input has"),
+        },
+      }
+    }
+  }
+
+  fn decode_err(input: &[u8]) -> String {
+    match decode(&[input]) {
+      Ok(_) => panic!("{:?} was decoded", String::from_utf8_lossy(input)),
+      Err(e) => e.to_string(),
     }
+  }
+
+  fn assert_body(body: &[u8], trailers: &Trailers) {
+    assert_eq!(body, b"hello world");
+    assert_eq!(trailers.len(), 2);
+    assert_eq!(trailers["expires"], b"never");
+    assert_eq!(trailers["x-sum"], b"1");
+  }
 
-    let data = buffer.split_to(size);
-    buffer.advance(7);
-    return Ok(ParseStatus::Chunk(true, data));
+  #[test]
+  fn split_at_every_byte_boundary() {
+    for at in 0..=BODY.len() {
+      let (body, trailers, rest) = decode(&[&BODY[..at], &BODY[at..]]).unwrap();
+      assert_body(&body, &trailers);
+      assert!(rest.is_empty());
+    }
   }
 
-  let data = buffer.split_to(size);
-  buffer.advance(2);
+  #[test]
+  fn one_byte_at_a_time() {
+    let parts: Vec<&[u8]> = BODY.chunks(1).collect();
+    let (body, trailers, rest) = decode(&parts).unwrap();
+    assert_body(&body, &trailers);
+    assert!(rest.is_empty());
+  }
 
-  return Ok(ParseStatus::Chunk(false, data));
+  #[test]
+  fn last_chunk_without_trailers() {
+    let (body, trailers, rest) = decode(&[b"0\r\n\r\n"]).unwrap();
+    assert!(body.is_empty());
+    assert!(trailers.is_empty());
+    assert!(rest.is_empty());
+  }
+
+  #[test]
+  fn last_chunk_with_trailers() {
+    let (body, trailers, _) = decode(&[b"3\r\nabc\r\n0\r\nA-Trailer: value\r\n\r\n"]).unwrap();
+    assert_eq!(body, b"abc");
+    assert_eq!(trailers["a-trailer"], b"value");
+  }
+
+  #[test]
+  fn data_after_last_chunk_is_left_in_buffer() {
+    let (_, _, rest) = decode(&[b"0\r\n\r\nGET / HTTP/1.1\r\n"]).unwrap();
+    assert_eq!(&rest[..], b"GET / HTTP/1.1\r\n");
+  }
+
+  #[test]
+  fn size_digits_limit() {
+    let (body, _, _) = decode(&[b"0000000000000003\r\nabc\r\n0\r\n\r\n"]).unwrap();
+    assert_eq!(body, b"abc");
+    assert_eq!(
+      decode_err(b"00000000000000003\r\n"),
+      "Chunk size is too long"
+    );
+  }
+
+  #[test]
+  fn extension_length_limit() {
+    let mut input = b"1;".to_vec();
+    input.extend_from_slice(&[b'a'; MAX_EXT_LEN]);
+    input.extend_from_slice(b"\r\nx\r\n0\r\n\r\n");
+    let (body, _, _) = decode(&[&input]).unwrap();
+    assert_eq!(body, b"x");
+
+    let mut input = b"1;".to_vec();
+    input.extend_from_slice(&[b'a'; MAX_EXT_LEN + 1]);
+    assert_eq!(decode_err(&input), "Chunk extension is too long");
+  }
+
+  #[test]
+  fn bare_lf_is_rejected() {
+    assert_eq!(decode_err(b"5\nhello\r\n0\r\n\r\n"), "Invalid chunk");
+    assert_eq!(
+      decode_err(b"5;ext\nhello\r\n0\r\n\r\n"),
+      "Invalid chunk extension"
+    );
+    assert_eq!(decode_err(b"5\r\nhello\n0\r\n\r\n"), "Invalid chunk");
+  }
+
+  #[test]
+  fn data_has_to_end_with_crlf() {
+    assert_eq!(decode_err(b"5\r\nhelloXX0\r\n\r\n"), "Invalid chunk");
+    assert_eq!(decode_err(b"5\r\nhello\rX0\r\n\r\n"), "Invalid chunk");
+  }
+
+  #[test]
+  fn invalid_size() {
+    assert_eq!(decode_err(b"x\r\n"), "Invalid chunk size");
+    assert_eq!(decode_err(b"\r\n"), "Invalid chunk size");
+  }
 }
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+// connection settings shared by all readers of a server
+#[derive(Clone)]
+pub struct Config {
+  // idle time between requests on keep-alive connection
+  pub keep_alive_timeout: Duration,
+  // time to receive complete request head from its first byte
+  pub header_timeout: Duration,
+  // max inactivity while reading request body
+  pub body_timeout: Duration,
+  // max time response write may stall on a full socket
+  pub write_timeout: Duration,
+  // request line limit, longer lines get 414
+  pub max_request_line: usize,
+  // header count and total head size limits, exceeding them gets 431
+  pub max_headers: usize,
+  pub max_header_bytes: usize,
+  // body size limit for both content-length and chunked bodies, exceeding it gets 413
+  pub max_body_size: usize,
+  // bytes read past the request currently handled, pipelined requests in them are
+  // answered in order and socket is not read again until they are served
+  pub max_read_ahead: usize,
+  // pipelined requests served back to back before read ahead stops, socket is then
+  // read only as far as the current request needs until client waits for responses.
+  // Earlier this limit closed the connection and dropped requests client had already
+  // sent, now reading just slows down
+  pub max_pipelined: usize,
+  // http2 streams client may have open at the same time
+  pub max_concurrent_streams: u32,
+}
+
+impl Config {
+  pub fn new() -> Config {
+    Config {
+      keep_alive_timeout: Duration::from_secs(10),
+      header_timeout: Duration::from_secs(10),
+      body_timeout: Duration::from_secs(10),
+      write_timeout: Duration::from_secs(30),
+      max_request_line: 8 * 1024,
+      max_headers: 100,
+      max_header_bytes: 16 * 1024,
+      max_body_size: 16 * 1024 * 1024,
+      max_read_ahead: 16 * 1024,
+      max_pipelined: 16,
+      max_concurrent_streams: 100,
+    }
+  }
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config::new()
+  }
+}
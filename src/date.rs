@@ -25,6 +25,20 @@ impl Time {
 thread_local!(static TIME_CACHE: RefCell<Time> = RefCell::new(Time::new()));
 
 pub fn set_date_header(buf: &mut BytesMut) {
+  with_date(|bytes| push(buf, bytes));
+}
+
+// value without header name and line ending, for http2 responses
+pub fn date_value() -> http::HeaderValue {
+  with_date(|bytes| {
+    http::HeaderValue::from_bytes(&bytes[6..35]).expect("date is a valid header value")
+  })
+}
+
+fn with_date<F, R>(f: F) -> R
+where
+  F: FnOnce(&[u8; 37]) -> R,
+{
   TIME_CACHE.with(|cache| {
     let mut cache = cache.borrow_mut();
 
@@ -41,8 +55,8 @@ pub fn set_date_header(buf: &mut BytesMut) {
       }
     };
 
-    push(buf, &cache.bytes);
-  });
+    f(&cache.bytes)
+  })
 }
 
 fn generate_date_header(v: &SystemTime) -> [u8; 37] {
@@ -158,8 +172,8 @@ fn generate_date_header(v: &SystemTime) -> [u8; 37] {
   buf[6] = wday[0];
   buf[7] = wday[1];
   buf[8] = wday[2];
-  buf[11] = b'0' + (day / 10) as u8;
-  buf[12] = b'0' + (day % 10) as u8;
+  buf[11] = b'0' + day / 10;
+  buf[12] = b'0' + day % 10;
   buf[14] = mon[0];
   buf[15] = mon[1];
   buf[16] = mon[2];
@@ -167,12 +181,12 @@ fn generate_date_header(v: &SystemTime) -> [u8; 37] {
   buf[19] = b'0' + (year / 100 % 10) as u8;
   buf[20] = b'0' + (year / 10 % 10) as u8;
   buf[21] = b'0' + (year % 10) as u8;
-  buf[23] = b'0' + (hour / 10) as u8;
-  buf[24] = b'0' + (hour % 10) as u8;
-  buf[26] = b'0' + (min / 10) as u8;
-  buf[27] = b'0' + (min % 10) as u8;
-  buf[29] = b'0' + (sec / 10) as u8;
-  buf[30] = b'0' + (sec % 10) as u8;
+  buf[23] = b'0' + hour / 10;
+  buf[24] = b'0' + hour % 10;
+  buf[26] = b'0' + min / 10;
+  buf[27] = b'0' + min % 10;
+  buf[29] = b'0' + sec / 10;
+  buf[30] = b'0' + sec % 10;
 
   buf
 }
@@ -0,0 +1,609 @@
+use super::*;
+
+use futures::stream::FuturesUnordered;
+use h2::server::{self as server, SendResponse};
+use h2::{Reason, RecvStream, SendStream};
+use http::header::{
+  HeaderValue, CONNECTION, CONTENT_LENGTH, DATE, HOST, TRANSFER_ENCODING, UPGRADE,
+};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// serve connection with http2 or http/1 reader, tls connections are routed by
+// negotiated ALPN protocol and plain ones by http2 connection preface (prior knowledge)
+pub fn serve<T, S>(socket: S, router: &T) -> Serve<T, S>
+where
+  T: RouterSearch,
+  S: transport::Transport,
+{
+  serve_with_config(socket, router, &config::Config::new())
+}
+
+pub fn serve_with_config<T, S>(socket: S, router: &T, config: &config::Config) -> Serve<T, S>
+where
+  T: RouterSearch,
+  S: transport::Transport,
+{
+  let alpn = socket.tls_info().and_then(|tls| tls.alpn);
+  let state = match alpn.as_deref() {
+    Some("h2") => ServeState::Http2(Box::new(Connection::with_config(
+      transport::Rewind::new(socket, BytesMut::new()),
+      router,
+      config,
+    ))),
+    Some(_) => ServeState::Http1(Box::new(reader::Reader::with_config(
+      transport::Rewind::new(socket, BytesMut::new()),
+      router,
+      config,
+    ))),
+    None => ServeState::Detect(Some(socket), BytesMut::with_capacity(PREFACE.len())),
+  };
+
+  Serve {
+    state,
+    router_raw: router as *const T,
+    timer: Delay::new(Instant::now() + config.keep_alive_timeout),
+    config: config.clone(),
+  }
+}
+
+pub struct Serve<T, S> {
+  state: ServeState<T, S>,
+  router_raw: *const T,
+  config: config::Config,
+  timer: Delay,
+}
+
+enum ServeState<T, S> {
+  Detect(Option<S>, BytesMut),
+  Http1(Box<reader::Reader<T, transport::Rewind<S>>>),
+  Http2(Box<Connection<T, transport::Rewind<S>>>),
+}
+
+impl<T, S> Future for Serve<T, S>
+where
+  T: RouterSearch,
+  S: transport::Transport,
+{
+  type Item = ();
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    let state = match &mut self.state {
+      ServeState::Http1(reader) => return reader.poll().map(|ready| ready.map(|_| ())),
+      ServeState::Http2(connection) => return connection.poll(),
+      ServeState::Detect(socket, buffer) => {
+        // read until preface is either matched or ruled out
+        while buffer.len() < PREFACE.len() && PREFACE.starts_with(buffer) {
+          match AsyncRead::read_buf(socket.as_mut().unwrap(), buffer)? {
+            Async::Ready(0) => return Ok(Async::Ready(())),
+            Async::Ready(_) => {}
+            Async::NotReady => {
+              // idle connection is closed silently as in reader
+              return match self.timer.poll().map_err(io::Error::other)? {
+                Async::Ready(_) => Ok(Async::Ready(())),
+                Async::NotReady => Ok(Async::NotReady),
+              };
+            }
+          }
+        }
+
+        let router = unsafe { &*self.router_raw };
+        let is_http2 = buffer.starts_with(PREFACE);
+        let socket = transport::Rewind::new(socket.take().unwrap(), buffer.take());
+
+        if is_http2 {
+          ServeState::Http2(Box::new(Connection::with_config(
+            socket,
+            router,
+            &self.config,
+          )))
+        } else {
+          ServeState::Http1(Box::new(reader::Reader::with_config(
+            socket,
+            router,
+            &self.config,
+          )))
+        }
+      }
+    };
+
+    self.state = state;
+    self.poll()
+  }
+}
+
+// http2 connection driver, every stream is dispatched through the router
+// with the same Request/Response api as http/1 requests (tcp sockets should have
+// nodelay set as small frames are otherwise held back by Nagle's algorithm)
+pub struct Connection<T, S> {
+  state: ConnectionState<S>,
+  streams: FuturesUnordered<StreamTask>,
+  dispatcher: Dispatcher<T>,
+  timer: Delay,
+  closing: bool,
+}
+
+// creates stream tasks, connection details are copied in to every request
+struct Dispatcher<T> {
+  router_raw: *const T,
+  config: config::Config,
+  peer_addr: Option<SocketAddr>,
+  local_addr: Option<SocketAddr>,
+  peer_cred: Option<transport::PeerCred>,
+  tls: Option<transport::TlsInfo>,
+}
+
+enum ConnectionState<S> {
+  Handshake(server::Handshake<S, Bytes>),
+  Serving(server::Connection<S, Bytes>),
+}
+
+impl<T, S> Connection<T, S>
+where
+  T: RouterSearch,
+  S: transport::Transport,
+{
+  pub fn new(socket: S, router: &T) -> Connection<T, S> {
+    Connection::with_config(socket, router, &config::Config::new())
+  }
+
+  pub fn with_config(socket: S, router: &T, config: &config::Config) -> Connection<T, S> {
+    let peer_addr = socket.peer_addr();
+    let local_addr = socket.local_addr();
+    let peer_cred = socket.peer_cred();
+    let tls = socket.tls_info();
+
+    let mut builder = server::Builder::new();
+    builder
+      .max_header_list_size(std::cmp::min(config.max_header_bytes, u32::MAX as usize) as u32)
+      .max_concurrent_streams(config.max_concurrent_streams);
+
+    Connection {
+      state: ConnectionState::Handshake(builder.handshake(socket)),
+      streams: FuturesUnordered::new(),
+      dispatcher: Dispatcher {
+        router_raw: router as *const T,
+        config: config.clone(),
+        peer_addr,
+        local_addr,
+        peer_cred,
+        tls,
+      },
+      timer: Delay::new(Instant::now() + config.header_timeout),
+      closing: false,
+    }
+  }
+}
+
+impl<T: RouterSearch> Dispatcher<T> {
+  fn dispatch(
+    &self,
+    request: http::Request<RecvStream>,
+    respond: SendResponse<Bytes>,
+  ) -> StreamTask {
+    let (parts, body) = request.into_parts();
+
+    let mut req = request::Request::new();
+    req.peer_addr = self.peer_addr;
+    req.local_addr = self.local_addr;
+    req.peer_cred = self.peer_cred;
+    req.tls = self.tls.clone();
+
+    req.reset_headers(parts.headers.len() + 1);
+    for (name, value) in parts.headers.iter() {
+      req.add_header(name.as_str().to_string(), value.as_bytes().to_vec());
+    }
+
+    // :authority replaces host header in http2
+    if !parts.headers.contains_key(HOST) {
+      if let Some(authority) = parts.uri.authority_part() {
+        req.add_header("host".to_string(), authority.as_str().as_bytes().to_vec());
+      }
+    }
+
+    // handlers get origin form uri as with http/1 requests
+    let uri = parts
+      .uri
+      .path_and_query()
+      .and_then(|path| path.as_str().parse::<Uri>().ok())
+      .unwrap_or_default();
+    req.init(2, parts.method.as_str().to_string(), uri, BytesMut::new());
+
+    let mut res = response::Response::with_writer(Box::new(StreamWriter::new(respond)));
+    res.write_timeout = self.config.write_timeout;
+
+    let fut = unsafe { (*self.router_raw).find((req, res)) };
+
+    StreamTask {
+      body: Some(body),
+      body_size: 0,
+      max_body_size: self.config.max_body_size,
+      body_timeout: self.config.body_timeout,
+      timer: Delay::new(Instant::now() + self.config.body_timeout),
+      req_func: OnData::Empty,
+      state: StreamState::Processing(fut),
+    }
+  }
+}
+
+impl<T, S> Future for Connection<T, S>
+where
+  T: RouterSearch,
+  S: transport::Transport,
+{
+  type Item = ();
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    loop {
+      let connection = match &mut self.state {
+        ConnectionState::Serving(connection) => connection,
+        ConnectionState::Handshake(handshake) => {
+          match handshake.poll().map_err(into_io)? {
+            Async::Ready(connection) => {
+              self.state = ConnectionState::Serving(connection);
+              self
+                .timer
+                .reset(Instant::now() + self.dispatcher.config.keep_alive_timeout);
+              continue;
+            }
+            Async::NotReady => {
+              // handshake has to complete within header timeout
+              return match self.timer.poll().map_err(io::Error::other)? {
+                Async::Ready(_) => Err(io::Error::new(ErrorKind::TimedOut, "Handshake timed out")),
+                Async::NotReady => Ok(Async::NotReady),
+              };
+            }
+          }
+        }
+      };
+
+      // accept new streams, polling connection also drives all its io
+      loop {
+        match connection.poll().map_err(into_io)? {
+          Async::Ready(Some((request, respond))) => {
+            let task = self.dispatcher.dispatch(request, respond);
+            self.streams.push(task);
+          }
+          Async::Ready(None) => return Ok(Async::Ready(())),
+          Async::NotReady => break,
+        }
+      }
+
+      // failed stream is reset, rest of the connection is not affected
+      while let Ok(Async::Ready(Some(()))) | Err(_) = self.streams.poll() {}
+
+      if !self.streams.is_empty() {
+        self
+          .timer
+          .reset(Instant::now() + self.dispatcher.config.keep_alive_timeout);
+        return Ok(Async::NotReady);
+      }
+
+      // idle connection is closed with goaway once keep alive timeout passes
+      match self.timer.poll().map_err(io::Error::other)? {
+        Async::Ready(_) if !self.closing => {
+          self.closing = true;
+          connection.graceful_shutdown();
+          self
+            .timer
+            .reset(Instant::now() + self.dispatcher.config.keep_alive_timeout);
+        }
+        // client did not complete graceful shutdown in time
+        Async::Ready(_) => return Ok(Async::Ready(())),
+        Async::NotReady => return Ok(Async::NotReady),
+      }
+    }
+  }
+}
+
+enum StreamState {
+  Empty,
+  Ready(ReqResTuple),
+  Processing(ReturnFuture),
+  // response is complete, remaining frames are flushed
+  Closing(ReqResTuple),
+}
+
+// single request stream, follows the same steps as reader does for http/1 request
+struct StreamTask {
+  body: Option<RecvStream>,
+  body_size: usize,
+  max_body_size: usize,
+  // max inactivity while handler waits for body data, connection keep alive does
+  // not run while streams are open
+  body_timeout: Duration,
+  timer: Delay,
+  req_func: OnData,
+  state: StreamState,
+}
+
+impl StreamTask {
+  fn respond_error(
+    &mut self,
+    req: request::Request,
+    mut res: response::Response,
+    status: StatusCode,
+  ) {
+    res.status(status);
+    let fut = res.write(Bytes::new()).map(|res| (req, res));
+    self.state = StreamState::Processing(Box::new(fut));
+  }
+
+  // stalled body gets 408 as in reader, started response can only be reset
+  fn body_timed_out(
+    &mut self,
+    req: request::Request,
+    res: response::Response,
+  ) -> Result<(), io::Error> {
+    self.body = None;
+    if res.started {
+      return Err(io::Error::new(
+        ErrorKind::TimedOut,
+        "Request body timed out",
+      ));
+    }
+
+    self.respond_error(req, res, StatusCode::REQUEST_TIMEOUT);
+    Ok(())
+  }
+
+  fn body_stalled(&mut self) -> Result<bool, io::Error> {
+    Ok(self.timer.poll().map_err(io::Error::other)?.is_ready())
+  }
+}
+
+impl Future for StreamTask {
+  type Item = ();
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    loop {
+      match mem::replace(&mut self.state, StreamState::Empty) {
+        StreamState::Empty => unreachable!(), // this should never be called
+        StreamState::Processing(mut fut) => match fut.poll()? {
+          Async::Ready((mut req, res)) => {
+            if req.has_function {
+              req.has_function = false;
+              self.req_func = mem::replace(&mut req.on_data, OnData::Empty);
+            }
+
+            // time spent in handler does not count as body inactivity
+            self.timer.reset(Instant::now() + self.body_timeout);
+            self.state = StreamState::Ready((req, res));
+          }
+          Async::NotReady => {
+            self.state = StreamState::Processing(fut);
+            return Ok(Async::NotReady);
+          }
+        },
+        StreamState::Ready((mut req, res)) => {
+          let body = match (&self.req_func, self.body.as_mut()) {
+            (OnData::Function(_), Some(body)) => body,
+            _ => {
+              // request is complete or handler does not read its body
+              if !res.started {
+                self.respond_error(req, res, StatusCode::INTERNAL_SERVER_ERROR);
+              } else {
+                self.state = StreamState::Closing((req, res));
+              }
+              continue;
+            }
+          };
+
+          match body.poll().map_err(into_io)? {
+            Async::Ready(Some(data)) => {
+              let _ = body.release_capacity().release_capacity(data.len());
+              req.is_last = body.is_end_stream();
+              self.timer.reset(Instant::now() + self.body_timeout);
+
+              self.body_size += data.len();
+              if self.body_size > self.max_body_size {
+                self.body = None;
+                if res.started {
+                  return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Request body is too large",
+                  ));
+                }
+
+                self.respond_error(req, res, StatusCode::PAYLOAD_TOO_LARGE);
+                continue;
+              }
+
+              req.data.extend_from_slice(&data);
+            }
+            Async::Ready(None) => {
+              // trailers are received after the last data frame
+              match body.poll_trailers().map_err(into_io)? {
+                Async::Ready(trailers) => {
+                  for (name, value) in trailers.iter().flatten() {
+                    req
+                      .trailers
+                      .insert(name.as_str().to_string(), value.as_bytes().to_vec());
+                  }
+                }
+                Async::NotReady if self.body_stalled()? => {
+                  self.body_timed_out(req, res)?;
+                  continue;
+                }
+                Async::NotReady => {
+                  self.state = StreamState::Ready((req, res));
+                  return Ok(Async::NotReady);
+                }
+              }
+
+              req.is_last = true;
+            }
+            Async::NotReady if self.body_stalled()? => {
+              self.body_timed_out(req, res)?;
+              continue;
+            }
+            Async::NotReady => {
+              self.state = StreamState::Ready((req, res));
+              return Ok(Async::NotReady);
+            }
+          }
+
+          if req.is_last {
+            self.body = None;
+          }
+
+          if let OnData::Function(f) = &self.req_func {
+            self.state = StreamState::Processing((f)((req, res)));
+          }
+        }
+        StreamState::Closing((req, mut res)) => match res.writer.poll_close()? {
+          Async::Ready(()) => return Ok(Async::Ready(())),
+          Async::NotReady => {
+            self.state = StreamState::Closing((req, res));
+            return Ok(Async::NotReady);
+          }
+        },
+      }
+    }
+  }
+}
+
+// sends response head and body of a single stream as http2 headers and data frames,
+// data is sent as far as the stream flow control window allows
+struct StreamWriter {
+  respond: SendResponse<Bytes>,
+  stream: Option<SendStream<Bytes>>,
+  head: Option<writer::Head>,
+  pending: Bytes,
+  // last data is queued
+  end: bool,
+  // stream is ended on our side
+  ended: bool,
+}
+
+impl StreamWriter {
+  fn new(respond: SendResponse<Bytes>) -> StreamWriter {
+    StreamWriter {
+      respond,
+      stream: None,
+      head: None,
+      pending: Bytes::new(),
+      end: false,
+      ended: false,
+    }
+  }
+
+  fn send_head(&mut self, head: writer::Head) -> io::Result<()> {
+    let mut response = http::Response::new(());
+    *response.status_mut() = head.status;
+    *response.headers_mut() = head.headers;
+
+    // connection specific headers are not allowed in http2, framing comes from the body
+    let headers = response.headers_mut();
+    for name in &[CONNECTION, TRANSFER_ENCODING, CONTENT_LENGTH, UPGRADE] {
+      headers.remove(name);
+    }
+    headers.remove("keep-alive");
+
+    if let Some(len) = head.content_length {
+      headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+    }
+    if !headers.contains_key(DATE) {
+      headers.insert(DATE, date::date_value());
+    }
+
+    // response without body ends the stream right away
+    let end = self.end && self.pending.is_empty();
+    let stream = self.respond.send_response(response, end).map_err(into_io)?;
+
+    self.stream = Some(stream);
+    self.ended = end;
+    Ok(())
+  }
+
+  // NotReady until all pending data fits in to flow control window
+  fn send_pending(&mut self) -> Poll<(), io::Error> {
+    let stream = match self.stream.as_mut() {
+      Some(stream) => stream,
+      None => return Ok(Async::Ready(())),
+    };
+
+    while !self.pending.is_empty() {
+      stream.reserve_capacity(self.pending.len());
+
+      let capacity = stream.capacity();
+      if capacity == 0 {
+        match stream.poll_capacity().map_err(into_io)? {
+          Async::Ready(Some(_)) => continue,
+          Async::Ready(None) => {
+            return Err(io::Error::new(ErrorKind::BrokenPipe, "Stream is closed"))
+          }
+          Async::NotReady => return Ok(Async::NotReady),
+        }
+      }
+
+      let amt = std::cmp::min(capacity, self.pending.len());
+      let data = self.pending.split_to(amt);
+      let end = self.end && self.pending.is_empty();
+
+      stream.send_data(data, end).map_err(into_io)?;
+      self.ended = end;
+    }
+
+    if self.end && !self.ended {
+      stream.send_data(Bytes::new(), true).map_err(into_io)?;
+      self.ended = true;
+    }
+
+    Ok(Async::Ready(()))
+  }
+}
+
+impl writer::ResponseWriter for StreamWriter {
+  fn head(&mut self, head: writer::Head) {
+    self.head = Some(head);
+  }
+
+  fn data(&mut self, data: Bytes, end: bool) {
+    debug_assert!(self.pending.is_empty());
+
+    self.pending = data;
+    self.end = end;
+  }
+
+  fn remaining(&self) -> usize {
+    self.pending.len()
+  }
+
+  fn poll_flush(&mut self) -> Poll<(), io::Error> {
+    if let Some(head) = self.head.take() {
+      self.send_head(head)?;
+    }
+
+    self.send_pending()
+  }
+
+  fn poll_close(&mut self) -> Poll<(), io::Error> {
+    // response which was not completed by handler can not be ended normally
+    if !self.end {
+      if let Some(stream) = self.stream.as_mut() {
+        stream.send_reset(Reason::INTERNAL_ERROR);
+      }
+
+      return Ok(Async::Ready(()));
+    }
+
+    self.poll_flush()
+  }
+}
+
+fn into_io(e: h2::Error) -> io::Error {
+  if e.is_io() {
+    e.into_io().unwrap()
+  } else {
+    io::Error::other(e)
+  }
+}
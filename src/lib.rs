@@ -1,26 +1,37 @@
 // common imports
-pub use bytes::{BufMut, BytesMut};
+pub use bytes::{BufMut, Bytes, BytesMut};
 pub use hashbrown;
-pub use http::Uri;
+pub use http::{Extensions, HeaderMap, StatusCode, Uri};
 pub use tokio::prelude::*;
 
 // modules (for now public)
 pub mod chunk;
+pub mod config;
 pub mod date;
+#[cfg(feature = "http2")]
+pub mod http2;
+pub mod pool;
+pub mod proxy;
 pub mod reader;
 pub mod request;
 pub mod response;
 pub mod router;
+pub mod sse;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
+pub mod upgrade;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 pub mod writer;
 
-pub(crate) type ReadHalf = tokio::io::ReadHalf<tokio::net::TcpStream>;
-pub(crate) type WriteHalf = tokio::io::WriteHalf<tokio::net::TcpStream>;
+pub(crate) type WriteHalf = Box<dyn transport::WriteTransport>;
 pub(crate) type ReqResTuple = (request::Request, response::Response);
 
 pub type ReturnFuture = Box<dyn Future<Item = ReqResTuple, Error = std::io::Error> + Send + Sync>;
 pub enum OnData {
   Empty,
-  Function(Box<Fn(ReqResTuple) -> ReturnFuture + Send + Sync>),
+  Function(Box<dyn Fn(ReqResTuple) -> ReturnFuture + Send + Sync>),
 }
 
 pub trait RouterSearch {
@@ -0,0 +1,121 @@
+use super::*;
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+// bigger buffers are freed instead of being kept in the pool
+const MAX_CAPACITY: usize = 16 * 1024;
+// at most 1 MiB is kept per thread
+const MAX_POOLED: usize = 64;
+// buffers which were not taken from the pool for this long are freed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Pool {
+  buffers: Vec<BytesMut>,
+  // fewest buffers left in the pool since last trim, nobody needed those
+  unused: usize,
+  trimmed: Instant,
+}
+
+impl Pool {
+  fn new() -> Pool {
+    Pool {
+      buffers: Vec::new(),
+      unused: 0,
+      trimmed: Instant::now(),
+    }
+  }
+
+  fn take(&mut self) -> Option<BytesMut> {
+    let buf = self.buffers.pop();
+    self.unused = std::cmp::min(self.unused, self.buffers.len());
+    buf
+  }
+
+  fn trim(&mut self, now: Instant) {
+    if now.duration_since(self.trimmed) < IDLE_TIMEOUT {
+      return;
+    }
+
+    let keep = self.buffers.len() - std::cmp::min(self.unused, self.buffers.len());
+    self.buffers.truncate(keep);
+    if self.buffers.is_empty() {
+      self.buffers.shrink_to_fit();
+    }
+
+    self.unused = self.buffers.len();
+    self.trimmed = now;
+  }
+}
+
+thread_local!(static POOL: RefCell<Pool> = RefCell::new(Pool::new()));
+
+pub fn get(capacity: usize) -> BytesMut {
+  let mut buf = POOL
+    .with(|pool| pool.borrow_mut().take())
+    .unwrap_or_default();
+
+  buf.reserve(capacity);
+  buf
+}
+
+// only buffers owning their allocation may be returned, capacity of a split view says
+// nothing about the allocation it keeps alive
+pub fn put(mut buf: BytesMut) {
+  if buf.capacity() > MAX_CAPACITY {
+    return;
+  }
+
+  buf.clear();
+  POOL.with(|pool| {
+    let mut pool = pool.borrow_mut();
+    pool.trim(Instant::now());
+    if pool.buffers.len() < MAX_POOLED {
+      pool.buffers.push(buf);
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pool(len: usize) -> Pool {
+    let mut pool = Pool::new();
+    pool.buffers = (0..len).map(|_| BytesMut::with_capacity(64)).collect();
+    pool.unused = len;
+    pool
+  }
+
+  #[test]
+  fn unused_buffers_are_freed_after_idle_timeout() {
+    let mut pool = pool(8);
+    let start = pool.trimmed;
+
+    // at most 3 buffers were in use at the same time
+    let taken: Vec<_> = (0..3).map(|_| pool.take().unwrap()).collect();
+    pool.buffers.extend(taken);
+
+    pool.trim(start + IDLE_TIMEOUT / 2);
+    assert_eq!(pool.buffers.len(), 8);
+
+    pool.trim(start + IDLE_TIMEOUT);
+    assert_eq!(pool.buffers.len(), 3);
+
+    // nothing was taken during next interval
+    pool.trim(start + IDLE_TIMEOUT * 2);
+    assert!(pool.buffers.is_empty());
+  }
+
+  #[test]
+  fn busy_pool_is_kept() {
+    let mut pool = pool(4);
+    let start = pool.trimmed;
+
+    let taken: Vec<_> = (0..4).map(|_| pool.take().unwrap()).collect();
+    pool.buffers.extend(taken);
+
+    pool.trim(start + IDLE_TIMEOUT);
+    assert_eq!(pool.buffers.len(), 4);
+  }
+}
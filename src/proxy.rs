@@ -0,0 +1,415 @@
+use super::*;
+
+use bytes::Buf;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// longest v1 header including CRLF
+const V1_MAX_LEN: usize = 107;
+
+type Addresses = Option<(SocketAddr, SocketAddr)>;
+
+// read HAProxy PROXY protocol (v1 or v2) header sent by load balancer in front of the
+// server, connections without valid header are rejected. There is no timeout of its own,
+// pass it to `Reader::accept` so header has to arrive within `header_timeout`
+pub fn accept<S>(socket: S) -> Accept<S>
+where
+  S: AsyncRead,
+{
+  Accept {
+    socket: Some(socket),
+    buf: BytesMut::new(),
+  }
+}
+
+pub struct Accept<S> {
+  socket: Option<S>,
+  buf: BytesMut,
+}
+
+impl<S> Future for Accept<S>
+where
+  S: AsyncRead,
+{
+  type Item = ProxyStream<S>;
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    loop {
+      if let Some((len, addresses)) = parse(&self.buf)? {
+        // bytes received after the header belong to the client
+        self.buf.advance(len);
+
+        let socket = self.socket.take().expect("Accept polled after completion");
+        return Ok(Async::Ready(ProxyStream {
+          io: transport::Rewind::new(socket, self.buf.take()),
+          addresses,
+        }));
+      }
+
+      let socket = self
+        .socket
+        .as_mut()
+        .expect("Accept polled after completion");
+      self.buf.reserve(512);
+      if futures::try_ready!(AsyncRead::read_buf(socket, &mut self.buf)) == 0 {
+        return Err(io::Error::new(
+          ErrorKind::UnexpectedEof,
+          "Connection closed before PROXY header",
+        ));
+      }
+    }
+  }
+}
+
+// connection with client and destination addresses taken from PROXY header,
+// LOCAL and UNKNOWN headers keep addresses of the socket itself
+pub struct ProxyStream<S> {
+  // data client sent together with the header is read first
+  io: transport::Rewind<S>,
+  addresses: Addresses,
+}
+
+impl<S: Read> Read for ProxyStream<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.io.read(buf)
+  }
+}
+
+impl<S: Write> Write for ProxyStream<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.io.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.io.flush()
+  }
+}
+
+impl<S: AsyncRead> AsyncRead for ProxyStream<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for ProxyStream<S> {
+  fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+    self.io.write_buf(buf)
+  }
+
+  fn poll_flush(&mut self) -> Poll<(), io::Error> {
+    self.io.poll_flush()
+  }
+
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    self.io.shutdown()
+  }
+}
+
+impl<S: transport::Transport> transport::Transport for ProxyStream<S> {
+  fn peer_addr(&self) -> Option<SocketAddr> {
+    match self.addresses {
+      Some((source, _)) => Some(source),
+      None => self.io.peer_addr(),
+    }
+  }
+
+  fn local_addr(&self) -> Option<SocketAddr> {
+    match self.addresses {
+      Some((_, destination)) => Some(destination),
+      None => self.io.local_addr(),
+    }
+  }
+
+  fn peer_cred(&self) -> Option<transport::PeerCred> {
+    self.io.peer_cred()
+  }
+
+  fn tls_info(&self) -> Option<transport::TlsInfo> {
+    self.io.tls_info()
+  }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// returns header length and addresses once complete header is buffered
+fn parse(buf: &[u8]) -> Result<Option<(usize, Addresses)>, io::Error> {
+  if buf.starts_with(V1_PREFIX) {
+    return parse_v1(buf);
+  }
+
+  if buf.starts_with(V2_SIGNATURE) {
+    return parse_v2(buf);
+  }
+
+  // not enough data to tell versions apart yet
+  if V1_PREFIX.starts_with(buf) || V2_SIGNATURE.starts_with(buf) {
+    return Ok(None);
+  }
+
+  Err(invalid("Invalid PROXY header"))
+}
+
+// "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n"
+fn parse_v1(buf: &[u8]) -> Result<Option<(usize, Addresses)>, io::Error> {
+  let end = match buf.iter().take(V1_MAX_LEN).position(|&b| b == b'\n') {
+    Some(end) => end,
+    None if buf.len() < V1_MAX_LEN => return Ok(None),
+    None => return Err(invalid("PROXY header is too long")),
+  };
+
+  if buf[end - 1] != b'\r' {
+    return Err(invalid("Invalid PROXY header"));
+  }
+
+  let line = std::str::from_utf8(&buf[V1_PREFIX.len()..end - 1])
+    .map_err(|_| invalid("Invalid PROXY header"))?;
+  let fields: Vec<&str> = line.split(' ').collect();
+
+  let addresses = match fields[0] {
+    // rest of the line is ignored for unknown protocols
+    "UNKNOWN" => None,
+    "TCP4" | "TCP6" if fields.len() == 5 => {
+      let ipv4 = fields[0] == "TCP4";
+      let source = v1_address(fields[1], fields[3], ipv4)?;
+      let destination = v1_address(fields[2], fields[4], ipv4)?;
+      Some((source, destination))
+    }
+    _ => return Err(invalid("Invalid PROXY header")),
+  };
+
+  Ok(Some((end + 1, addresses)))
+}
+
+fn v1_address(ip: &str, port: &str, ipv4: bool) -> Result<SocketAddr, io::Error> {
+  let ip: IpAddr = ip.parse().map_err(|_| invalid("Invalid PROXY address"))?;
+  let port: u16 = port.parse().map_err(|_| invalid("Invalid PROXY port"))?;
+
+  if ip.is_ipv4() != ipv4 {
+    return Err(invalid("Invalid PROXY address"));
+  }
+
+  Ok(SocketAddr::new(ip, port))
+}
+
+// 12 byte signature, version and command, address family, length and addresses
+// followed by optional TLVs which are skipped
+fn parse_v2(buf: &[u8]) -> Result<Option<(usize, Addresses)>, io::Error> {
+  if buf.len() < 16 {
+    return Ok(None);
+  }
+
+  if buf[12] >> 4 != 2 {
+    return Err(invalid("Unsupported PROXY protocol version"));
+  }
+
+  let len = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+  if buf.len() < len {
+    return Ok(None);
+  }
+
+  let body = &buf[16..len];
+  let addresses = match (buf[12] & 0x0f, buf[13] >> 4) {
+    // LOCAL, health checks from the balancer itself
+    (0, _) => None,
+    (1, 1) if body.len() >= 12 => Some(v2_addresses(body, 4)),
+    (1, 2) if body.len() >= 36 => Some(v2_addresses(body, 16)),
+    (1, 1) | (1, 2) => return Err(invalid("Invalid PROXY address")),
+    // unspecified and unix families carry no client ip
+    (1, _) => None,
+    _ => return Err(invalid("Unsupported PROXY command")),
+  };
+
+  Ok(Some((len, addresses)))
+}
+
+// source and destination ips followed by source and destination ports
+fn v2_addresses(body: &[u8], ip_len: usize) -> (SocketAddr, SocketAddr) {
+  let ip = |octets: &[u8]| -> IpAddr {
+    if ip_len == 4 {
+      let mut ip = [0; 4];
+      ip.copy_from_slice(octets);
+      Ipv4Addr::from(ip).into()
+    } else {
+      let mut ip = [0; 16];
+      ip.copy_from_slice(octets);
+      Ipv6Addr::from(ip).into()
+    }
+  };
+
+  let ports = &body[ip_len * 2..];
+  (
+    SocketAddr::new(
+      ip(&body[..ip_len]),
+      u16::from_be_bytes([ports[0], ports[1]]),
+    ),
+    SocketAddr::new(
+      ip(&body[ip_len..ip_len * 2]),
+      u16::from_be_bytes([ports[2], ports[3]]),
+    ),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+  }
+
+  fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.push(0x20 | command);
+    header.push(family << 4 | 1);
+    header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    header.extend_from_slice(body);
+    header
+  }
+
+  #[test]
+  fn v1_tcp4() {
+    let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET";
+    let (len, addresses) = parse(header).unwrap().unwrap();
+
+    assert_eq!(len, header.len() - 3);
+    assert_eq!(
+      addresses,
+      Some((addr("192.0.2.1:56324"), addr("198.51.100.1:443")))
+    );
+  }
+
+  #[test]
+  fn v1_tcp6() {
+    let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+
+    assert_eq!(
+      parse(header).unwrap(),
+      Some((
+        header.len(),
+        Some((addr("[2001:db8::1]:56324"), addr("[2001:db8::2]:443")))
+      ))
+    );
+  }
+
+  #[test]
+  fn v1_unknown_keeps_socket_addresses() {
+    let header = b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n";
+    assert_eq!(parse(header).unwrap(), Some((header.len(), None)));
+    assert_eq!(parse(b"PROXY UNKNOWN\r\n").unwrap(), Some((15, None)));
+  }
+
+  #[test]
+  fn v1_rejects_invalid_lines() {
+    assert!(parse(b"PROXY TCP4 2001:db8::1 198.51.100.1 56324 443\r\n").is_err());
+    assert!(parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n").is_err());
+    assert!(parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 70000\r\n").is_err());
+    assert!(parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\n").is_err());
+    assert!(parse(b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n").is_err());
+    assert!(parse(b"GET / HTTP/1.1\r\n\r\n").is_err());
+  }
+
+  #[test]
+  fn v1_length_limit() {
+    let mut header = b"PROXY UNKNOWN ".to_vec();
+    header.resize(V1_MAX_LEN - 2, b'x');
+    header.extend_from_slice(b"\r\n");
+    assert_eq!(parse(&header).unwrap(), Some((V1_MAX_LEN, None)));
+
+    let mut header = b"PROXY UNKNOWN ".to_vec();
+    header.resize(V1_MAX_LEN - 1, b'x');
+    header.extend_from_slice(b"\r\n");
+    assert!(parse(&header).is_err());
+    assert!(parse(&header[..V1_MAX_LEN]).is_err());
+  }
+
+  #[test]
+  fn incomplete_headers_wait_for_more_data() {
+    assert_eq!(parse(b"").unwrap(), None);
+    assert_eq!(parse(b"PRO").unwrap(), None);
+    assert_eq!(parse(b"PROXY TCP4 192.0.2.1").unwrap(), None);
+    assert_eq!(parse(&V2_SIGNATURE[..5]).unwrap(), None);
+
+    let header = v2(
+      1,
+      1,
+      &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb],
+    );
+    for len in 0..header.len() {
+      assert_eq!(parse(&header[..len]).unwrap(), None);
+    }
+  }
+
+  #[test]
+  fn v2_local() {
+    let header = v2(0, 0, &[]);
+    assert_eq!(parse(&header).unwrap(), Some((16, None)));
+  }
+
+  #[test]
+  fn v2_inet_with_tlvs() {
+    let mut body = vec![192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+    // PP2_TYPE_AUTHORITY "example.com" followed by PP2_TYPE_NOOP
+    body.extend_from_slice(&[0x02, 0x00, 0x0b]);
+    body.extend_from_slice(b"example.com");
+    body.extend_from_slice(&[0x04, 0x00, 0x00]);
+    let mut header = v2(1, 1, &body);
+    header.extend_from_slice(b"GET");
+
+    assert_eq!(
+      parse(&header).unwrap(),
+      Some((
+        header.len() - 3,
+        Some((addr("192.0.2.1:56324"), addr("198.51.100.1:443")))
+      ))
+    );
+  }
+
+  #[test]
+  fn v2_inet6() {
+    let mut body = Vec::new();
+    body.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+    body.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+    body.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+    body.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+    let header = v2(1, 2, &body);
+
+    assert_eq!(
+      parse(&header).unwrap(),
+      Some((
+        header.len(),
+        Some((addr("[2001:db8::1]:56324"), addr("[2001:db8::2]:443")))
+      ))
+    );
+  }
+
+  #[test]
+  fn v2_short_address_block() {
+    assert!(parse(&v2(1, 1, &[192, 0, 2, 1, 198, 51, 100, 1])).is_err());
+    assert!(parse(&v2(1, 2, &[0; 35])).is_err());
+  }
+
+  #[test]
+  fn v2_unix_and_unspecified_keep_socket_addresses() {
+    assert_eq!(parse(&v2(1, 0, &[])).unwrap(), Some((16, None)));
+    assert_eq!(parse(&v2(1, 3, &[0; 216])).unwrap(), Some((232, None)));
+  }
+
+  #[test]
+  fn v2_rejects_unknown_version_and_command() {
+    let mut header = v2(1, 1, &[0; 12]);
+    header[12] = 0x11;
+    assert!(parse(&header).is_err());
+    assert!(parse(&v2(2, 1, &[0; 12])).is_err());
+  }
+
+  #[test]
+  fn data_after_header_is_read_first() {
+    let data = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n\r\n".to_vec();
+    let mut stream = accept(io::Cursor::new(data)).wait().unwrap();
+
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"GET / HTTP/1.1\r\n\r\n");
+  }
+}
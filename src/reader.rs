@@ -1,8 +1,13 @@
 use super::*;
 
-use std::time::{Duration, Instant};
+use std::mem;
+use std::time::Instant;
 use tokio::timer::Delay;
 
+const BUFFER_SIZE: usize = 1024;
+// largest single read, bigger bodies are received in several reads
+const MAX_READ: usize = 64 * 1024;
+
 #[derive(PartialEq)]
 enum ReadState {
   Body,
@@ -14,58 +19,263 @@ enum ProcessState {
   Empty,
   Ready(ReqResTuple),
   Processing(ReturnFuture),
+  // connection is closed once transport flushed the last response
+  Closing(ReqResTuple),
 }
 
-pub struct Reader<T> {
-  socket: ReadHalf,
+pub struct Reader<T, S = tokio::net::TcpStream> {
+  // taken by upgraded connection
+  socket: Option<tokio::io::ReadHalf<S>>,
   buffer: BytesMut,
+  // parts were split off to requests and keep the whole allocation alive
+  buffer_shared: bool,
   req_func: OnData,
   body_size: usize,
+  chunked_size: usize,
+  // request was passed to the router and its response is not checked yet
+  dispatched: bool,
+  // requests served one after another without buffer running empty
+  pipelined: usize,
+  missing_response: bool,
+  chunk_decoder: chunk::Decoder,
   read_state: ReadState,
   router_raw: *const T,
   process_state: ProcessState,
-  keep_alive_timer: Delay,
+  config: config::Config,
+  timer: Delay,
 }
 
-impl<T> Reader<T>
+impl<T, S> Reader<T, S>
 where
   T: RouterSearch,
+  S: transport::Transport,
 {
-  pub fn new((socket, write_socket): (ReadHalf, WriteHalf), router: &T) -> Reader<T> {
+  pub fn new(socket: S, router: &T) -> Reader<T, S> {
+    Reader::with_config(socket, router, &config::Config::new())
+  }
+
+  pub fn with_config(socket: S, router: &T, config: &config::Config) -> Reader<T, S> {
+    // capture addresses before splitting as halves do not expose them
+    let mut req = request::Request::new();
+    req.peer_addr = socket.peer_addr();
+    req.local_addr = socket.local_addr();
+    req.peer_cred = socket.peer_cred();
+    req.tls = socket.tls_info();
+
+    let (socket, write_socket) = socket.split();
+    let mut res = response::Response::new(write_socket);
+    res.write_timeout = config.write_timeout;
+
     Reader {
-      socket,
-      buffer: BytesMut::with_capacity(1024),
+      socket: Some(socket),
+      buffer: pool::get(BUFFER_SIZE),
+      buffer_shared: false,
       req_func: OnData::Empty,
       body_size: 0,
+      chunked_size: 0,
+      dispatched: false,
+      pipelined: 0,
+      missing_response: false,
+      chunk_decoder: chunk::Decoder::new(),
       router_raw: router as *const T,
       read_state: ReadState::Request,
-      keep_alive_timer: Delay::new(Instant::now() + Duration::from_secs(10)),
-      process_state: ProcessState::Ready((
-        request::Request::new(),
-        response::Response::new(write_socket),
-      )),
+      timer: Delay::new(Instant::now() + config.keep_alive_timeout),
+      config: config.clone(),
+      process_state: ProcessState::Ready((req, res)),
+    }
+  }
+
+  // serve connection once transport handshake (tls, etc) is complete
+  pub fn accept<F>(handshake: F, router: &T) -> Connecting<T, F>
+  where
+    F: Future<Item = S, Error = std::io::Error>,
+  {
+    Reader::accept_with_config(handshake, router, &config::Config::new())
+  }
+
+  pub fn accept_with_config<F>(
+    handshake: F,
+    router: &T,
+    config: &config::Config,
+  ) -> Connecting<T, F>
+  where
+    F: Future<Item = S, Error = std::io::Error>,
+  {
+    Connecting {
+      state: ConnectState::Handshake(handshake),
+      router_raw: router as *const T,
+      timer: Delay::new(Instant::now() + config.header_timeout),
+      config: config.clone(),
+    }
+  }
+}
+
+enum ConnectState<T, F: Future> {
+  Handshake(F),
+  Reading(Box<Reader<T, F::Item>>),
+}
+
+pub struct Connecting<T, F: Future> {
+  state: ConnectState<T, F>,
+  router_raw: *const T,
+  config: config::Config,
+  timer: Delay,
+}
+
+impl<T, F> Future for Connecting<T, F>
+where
+  T: RouterSearch,
+  F: Future<Error = std::io::Error>,
+  F::Item: transport::Transport,
+{
+  type Item = ReqResTuple;
+  type Error = std::io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    loop {
+      match &mut self.state {
+        ConnectState::Handshake(handshake) => match handshake.poll()? {
+          Async::Ready(socket) => {
+            let router = unsafe { &*self.router_raw };
+            self.state =
+              ConnectState::Reading(Box::new(Reader::with_config(socket, router, &self.config)));
+          }
+          Async::NotReady => {
+            // handshake has to complete within header timeout
+            match self.timer.poll().map_err(std::io::Error::other)? {
+              Async::Ready(_) => {
+                return Err(std::io::Error::new(
+                  std::io::ErrorKind::TimedOut,
+                  "Handshake timed out",
+                ));
+              }
+              Async::NotReady => return Ok(Async::NotReady),
+            }
+          }
+        },
+        ConnectState::Reading(reader) => return reader.poll(),
+      }
+    }
+  }
+}
+
+impl<T, S> Reader<T, S> {
+  fn reset_timer(&mut self) {
+    let timeout = match self.read_state {
+      ReadState::Request if self.buffer.is_empty() => self.config.keep_alive_timeout,
+      ReadState::Request => self.config.header_timeout,
+      _ => self.config.body_timeout,
+    };
+
+    self.timer.reset(Instant::now() + timeout);
+  }
+
+  // split buffer may pin a large body allocation through its views, it is freed
+  // instead of being pooled
+  fn release_buffer(&mut self) {
+    let buffer = mem::replace(&mut self.buffer, BytesMut::new());
+    if !mem::replace(&mut self.buffer_shared, false) {
+      pool::put(buffer);
+    }
+  }
+
+  // pass socket and already buffered data to the upgrade handler
+  fn hand_off(&mut self, req: &mut request::Request, res: &mut response::Response)
+  where
+    S: transport::Transport,
+  {
+    let buffer = mem::replace(&mut self.buffer, BytesMut::new());
+
+    if let (Some(sender), Some(read), Some(write)) = (
+      req.on_upgrade.take(),
+      self.socket.take(),
+      res.writer.detach(),
+    ) {
+      let _ = sender.send(upgrade::Upgraded {
+        read: transport::Rewind::new(Box::new(read), buffer),
+        write: upgrade::UpgradedWrite::new(write),
+      });
+    }
+  }
+
+  // reads rest of known body plus at most max_read_ahead bytes, anything past the
+  // current request stays buffered until previous responses are written. Once
+  // max_pipelined requests were served back to back nothing is read ahead
+  fn read(&mut self) -> Poll<usize, std::io::Error>
+  where
+    S: transport::Transport,
+  {
+    let needed = match self.read_state {
+      ReadState::Body => self.body_size.saturating_sub(self.buffer.len()),
+      _ => 0,
+    };
+    let read_ahead = if self.pipelined < self.config.max_pipelined {
+      self.config.max_read_ahead
+    } else {
+      0
+    };
+    let limit = needed.saturating_add(read_ahead);
+    let limit = limit.clamp(1, MAX_READ);
+
+    if self.buffer.remaining_mut() < limit {
+      self.buffer.reserve(limit);
     }
+
+    let socket = self.socket.as_mut().expect("Reader polled after upgrade");
+    unsafe {
+      let buf = &mut self.buffer.bytes_mut()[..limit];
+      socket.prepare_uninitialized_buffer(buf);
+      let amt = futures::try_ready!(socket.poll_read(buf));
+      self.buffer.advance_mut(amt);
+      Ok(Async::Ready(amt))
+    }
+  }
+
+  fn close(&mut self, req: request::Request, res: response::Response) {
+    self.timer.reset(Instant::now() + self.config.write_timeout);
+    self.process_state = ProcessState::Closing((req, res));
+  }
+
+  // send error response and close connection once it is written
+  fn respond_error(
+    &mut self,
+    req: request::Request,
+    mut res: response::Response,
+    status: StatusCode,
+  ) {
+    self.read_state = ReadState::Request;
+
+    res.status(status);
+    res.close();
+    let fut = res.write(Bytes::new()).map(|res| (req, res));
+    self.process_state = ProcessState::Processing(Box::new(fut));
   }
 }
 
-impl<T> Future for Reader<T>
+impl<T, S> Future for Reader<T, S>
 where
   T: RouterSearch,
+  S: transport::Transport,
 {
   type Item = ReqResTuple;
   type Error = std::io::Error;
 
   fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-    // dbg!(self.timer.poll());
     loop {
       match std::mem::replace(&mut self.process_state, ProcessState::Empty) {
         ProcessState::Empty => unreachable!(), // this should never be called
         ProcessState::Processing(mut fut) => {
+          // keep alive timer is not polled while response is in progress,
+          // long lived responses (streams, sse) are not closed by it
           match fut.poll()? {
-            Async::Ready((mut req, res)) => {
-              self
-                .keep_alive_timer
-                .reset(Instant::now() + Duration::from_secs(10));
+            Async::Ready((mut req, mut res)) => {
+              // reader finishes without closing the socket, it belongs to upgrade handler now
+              if res.upgraded {
+                self.hand_off(&mut req, &mut res);
+                return Ok(Async::Ready((req, res)));
+              }
+
+              self.reset_timer();
               // fetch function from request in to the reader for easier execution
               if req.has_function {
                 req.has_function = false;
@@ -80,6 +290,29 @@ where
             }
           }
         }
+        ProcessState::Closing((req, mut res)) => {
+          // socket may be already closed by the client, there is nothing to do then
+          if let Ok(Async::NotReady) = res.writer.poll_close() {
+            // client which stopped reading is dropped after write timeout
+            if self.timer.poll().map_err(std::io::Error::other)?.is_ready() {
+              return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Response write timed out",
+              ));
+            }
+
+            self.process_state = ProcessState::Closing((req, res));
+            return Ok(Async::NotReady);
+          }
+
+          if self.missing_response {
+            return Err(std::io::Error::other(
+              "Handler finished without writing a response",
+            ));
+          }
+
+          return Ok(Async::Ready((req, res)));
+        }
         ProcessState::Ready((mut req, mut res)) => {
           loop {
             // check what reading state we are in
@@ -92,19 +325,56 @@ where
                   match &self.req_func {
                     OnData::Function(f) => {
                       req.data = self.buffer.split_to(self.body_size);
+                      self.buffer_shared = true;
                       req.is_last = true;
                       let fut = (f)((req, res));
                       self.process_state = ProcessState::Processing(fut.into_future());
                       break;
                     }
-                    OnData::Empty => self.buffer.advance(self.body_size), // free data
+                    OnData::Empty => {
+                      // free data and continue with the rest of the buffer
+                      self.buffer.advance(self.body_size);
+                      self.reset_timer();
+                      continue;
+                    }
                   }
                 }
               }
               ReadState::Chunk => {
-                if self.buffer.len() > 0 {
-                  match chunk::parse(&mut self.buffer)? {
+                if !self.buffer.is_empty() {
+                  // chunk data is split off the buffer
+                  self.buffer_shared = true;
+                  let status = match self
+                    .chunk_decoder
+                    .decode(&mut self.buffer, &mut req.trailers)
+                  {
+                    Ok(status) => status,
+                    Err(e) => {
+                      // response can not be sent once handler has started writing
+                      if res.started {
+                        return Err(e);
+                      }
+
+                      self.respond_error(req, res, StatusCode::BAD_REQUEST);
+                      break;
+                    }
+                  };
+
+                  match status {
                     chunk::ParseStatus::Chunk(is_last, data) => {
+                      self.chunked_size += data.len();
+                      if self.chunked_size > self.config.max_body_size {
+                        if res.started {
+                          return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Request body is too large",
+                          ));
+                        }
+
+                        self.respond_error(req, res, StatusCode::PAYLOAD_TOO_LARGE);
+                        break;
+                      }
+
                       if is_last {
                         req.is_last = is_last;
                         self.read_state = ReadState::Request;
@@ -117,7 +387,11 @@ where
                           self.process_state = ProcessState::Processing(fut.into_future());
                           break;
                         }
-                        OnData::Empty => {} // we can skip this data
+                        OnData::Empty => {
+                          // skip data and decode rest of the buffer
+                          self.reset_timer();
+                          continue;
+                        }
                       }
                     }
                     chunk::ParseStatus::NotEnoughData => {} // wait for more data
@@ -125,94 +399,185 @@ where
                 }
               }
               ReadState::Request => {
-                let mut headers = [httparse::EMPTY_HEADER; 50];
+                // previous request is complete and its response was sent
+                if self.dispatched {
+                  self.dispatched = false;
+
+                  // next response would be matched to this request by client, connection
+                  // has to be closed
+                  if !res.started {
+                    self.missing_response = true;
+                    self.respond_error(req, res, StatusCode::INTERNAL_SERVER_ERROR);
+                    break;
+                  }
+
+                  // data left in buffer means next request was pipelined
+                  if self.buffer.is_empty() {
+                    self.pipelined = 0;
+                  } else {
+                    self.pipelined += 1;
+                  }
+                }
+
+                if !res.keep_alive {
+                  self.close(req, res);
+                  break;
+                }
+
+                // request line has to end within the limit
+                let line_limit = std::cmp::min(self.buffer.len(), self.config.max_request_line + 1);
+                if self.buffer.len() > self.config.max_request_line
+                  && !self.buffer[..line_limit].contains(&b'\n')
+                {
+                  self.respond_error(req, res, StatusCode::URI_TOO_LONG);
+                  break;
+                }
+
+                let mut headers = vec![httparse::EMPTY_HEADER; self.config.max_headers];
                 let mut r = httparse::Request::new(&mut headers);
 
                 // parse available data
                 match r.parse(&self.buffer) {
-                  Ok(httparse::Status::Partial) => {} // continue reading (not enough data)
+                  Ok(httparse::Status::Partial) => {
+                    // continue reading (not enough data)
+                    if self.buffer.len() > self.config.max_header_bytes {
+                      self.respond_error(req, res, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+                      break;
+                    }
+                  }
                   Ok(httparse::Status::Complete(amt)) => {
+                    if amt > self.config.max_header_bytes {
+                      self.respond_error(req, res, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+                      break;
+                    }
+
                     // we need to reset old body size and headers
                     self.body_size = 0;
+                    self.chunked_size = 0;
                     req.reset_headers(r.headers.len());
 
                     // always assume that we have data (even if there is no data)
                     self.read_state = ReadState::Body;
 
+                    // HTTP/1.1 connections are persistent by default, HTTP/1.0 only on request
+                    let version = r.version.unwrap();
+                    let mut close = false;
+                    let mut keep_alive = false;
+                    let mut invalid = false;
+                    let mut framing = Framing::new();
+
                     for header in r.headers.iter() {
                       // make all header's names the same case
                       let header_name = header.name.to_lowercase();
 
-                      if self.read_state != ReadState::Chunk {
-                        if header_name == "transfer-encoding" {
-                          if &header.value[header.value.len() - 7..header.value.len()] == b"chunked"
-                          {
-                            self.read_state = ReadState::Chunk;
-                          }
-                        } else if header_name == "content-length" {
-                          //TODO: need to handle errors properly
-                          self.body_size = std::str::from_utf8(header.value)
-                            .expect("Wrong value in header")
-                            .parse::<usize>()
-                            .expect("Could not parse usize");
-                        }
+                      if header_name == "transfer-encoding" {
+                        framing.add_transfer_encoding(header.value);
+                      } else if header_name == "content-length" {
+                        framing.add_content_length(header.value);
                       }
 
-                      let mut buf = Vec::with_capacity(header.value.len());
-                      unsafe {
-                        // we can do unsafe copy here :)
-                        buf.bytes_mut()[..header.value.len()].copy_from_slice(header.value)
-                      };
-                      req.add_header(header_name, buf);
+                      if header_name == "connection" {
+                        close |= response::has_token(header.value, b"close");
+                        keep_alive |= response::has_token(header.value, b"keep-alive");
+                      }
+
+                      req.add_header(header_name, header.value.to_vec());
+                    }
+
+                    let uri = match r.path.unwrap().parse::<Uri>() {
+                      Ok(uri) => uri,
+                      Err(_e) => {
+                        invalid = true;
+                        Uri::default()
+                      }
+                    };
+
+                    if invalid {
+                      self.respond_error(req, res, StatusCode::BAD_REQUEST);
+                      break;
+                    }
+
+                    match framing.body() {
+                      Ok(Some(size)) => self.body_size = size,
+                      Ok(None) => {
+                        self.read_state = ReadState::Chunk;
+                        self.chunk_decoder.reset();
+                      }
+                      Err(status) => {
+                        self.respond_error(req, res, status);
+                        break;
+                      }
+                    }
+
+                    if self.body_size > self.config.max_body_size {
+                      self.respond_error(req, res, StatusCode::PAYLOAD_TOO_LARGE);
+                      break;
                     }
 
                     // empty previous function
                     self.req_func = OnData::Empty;
 
+                    res.version = version;
+                    res.keep_alive = !close && (version > 0 || keep_alive);
+                    res.started = false;
+
                     let method = r.method.unwrap().to_string();
-                    let version = r.version.unwrap();
-                    req.init(
-                      version,
-                      method,
-                      r.path.unwrap().parse::<Uri>().unwrap(),
-                      self.buffer.split_to(amt),
-                    );
+                    req.init(version, method, uri, self.buffer.split_to(amt));
+                    self.buffer_shared = true;
 
+                    self.dispatched = true;
                     let fut = unsafe { (*self.router_raw).find((req, res)) };
                     self.process_state = ProcessState::Processing(fut.into_future());
                     break;
                   }
+                  Err(httparse::Error::TooManyHeaders) => {
+                    self.respond_error(req, res, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+                    break;
+                  }
                   Err(_e) => {
-                    return Err(std::io::Error::new(
-                      std::io::ErrorKind::Other,
-                      "Could not parse request",
-                    ));
+                    self.respond_error(req, res, StatusCode::BAD_REQUEST);
+                    break;
                   }
                 }
               }
             }
 
-            if !self.buffer.has_remaining_mut() {
-              self.buffer.reserve(1024);
+            if self.buffer.is_empty() && self.buffer.capacity() < BUFFER_SIZE {
+              // buffer was released while connection was idle
+              self.buffer = pool::get(BUFFER_SIZE);
             }
 
-            match self.socket.read_buf(&mut self.buffer)? {
+            let was_empty = self.buffer.is_empty();
+            match self.read()? {
               // 0 socket is closed :)
               Async::Ready(0) => return Ok(Async::Ready((req, res))),
               // We have some data need to check it in next iter
               Async::Ready(_) => {
-                self
-                  .keep_alive_timer
-                  .reset(Instant::now() + Duration::from_secs(10));
+                // head timeout starts with its first byte and is not extended by next reads
+                if self.read_state != ReadState::Request || was_empty {
+                  self.reset_timer();
+                }
               }
               Async::NotReady => {
-                // TODO: handle unwrap properly
-                match self.keep_alive_timer.poll().unwrap() {
+                match self.timer.poll().map_err(std::io::Error::other)? {
                   Async::Ready(_) => {
-                    res.shutdown();
-                    return Ok(Async::Ready((req, res)));
+                    // idle connection is closed silently, slow request gets 408 unless
+                    // its response has already started
+                    let started = self.dispatched && res.started;
+                    if (self.read_state == ReadState::Request && was_empty) || started {
+                      self.close(req, res);
+                      break;
+                    }
+
+                    self.respond_error(req, res, StatusCode::REQUEST_TIMEOUT);
+                    break;
                   }
                   Async::NotReady => {
+                    // do not hold memory for idle connections
+                    if self.buffer.is_empty() {
+                      self.release_buffer();
+                    }
+
                     // nothing has been read set our state to ready to process new data in next wake up
                     self.process_state = ProcessState::Ready((req, res));
                     return Ok(Async::NotReady);
@@ -226,3 +591,217 @@ where
     }
   }
 }
+
+// message body framing rules from RFC 7230 section 3.3.3, anything ambiguous
+// is rejected as it could be interpreted differently by a proxy in front of us
+struct Framing {
+  content_length: Option<usize>,
+  transfer_encoding: bool,
+  chunked: bool,
+  invalid: bool,
+  unsupported: bool,
+}
+
+impl Framing {
+  fn new() -> Framing {
+    Framing {
+      content_length: None,
+      transfer_encoding: false,
+      chunked: false,
+      invalid: false,
+      unsupported: false,
+    }
+  }
+
+  fn add_transfer_encoding(&mut self, value: &[u8]) {
+    self.transfer_encoding = true;
+
+    for coding in value.split(|&b| b == b',') {
+      let coding = response::trim(coding);
+      if coding.is_empty() {
+        continue;
+      }
+
+      // chunked has to be applied exactly once and be the last coding
+      if self.chunked {
+        self.invalid = true;
+      }
+
+      if coding.eq_ignore_ascii_case(b"chunked") {
+        self.chunked = true;
+      } else {
+        self.unsupported = true;
+      }
+    }
+  }
+
+  fn add_content_length(&mut self, value: &[u8]) {
+    // duplicates (in list or separate headers) are accepted only with the same value
+    for part in value.split(|&b| b == b',') {
+      match (
+        parse_content_length(response::trim(part)),
+        self.content_length,
+      ) {
+        (Some(size), None) => self.content_length = Some(size),
+        (Some(size), Some(current)) if size == current => {}
+        _ => self.invalid = true,
+      }
+    }
+  }
+
+  // Some(size) for fixed length body, None for chunked body
+  fn body(&self) -> Result<Option<usize>, StatusCode> {
+    if self.invalid || (self.transfer_encoding && self.content_length.is_some()) {
+      return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if self.unsupported {
+      return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+
+    if self.transfer_encoding {
+      if !self.chunked {
+        return Err(StatusCode::BAD_REQUEST);
+      }
+
+      return Ok(None);
+    }
+
+    Ok(Some(self.content_length.unwrap_or(0)))
+  }
+}
+
+// content-length is a plain decimal number, overflow is treated as invalid
+fn parse_content_length(value: &[u8]) -> Option<usize> {
+  if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+    return None;
+  }
+
+  std::str::from_utf8(value).ok()?.parse::<usize>().ok()
+}
+
+impl<T, S> Drop for Reader<T, S> {
+  fn drop(&mut self) {
+    self.release_buffer();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn framing(headers: &[(&str, &str)]) -> Result<Option<usize>, StatusCode> {
+    let mut framing = Framing::new();
+    for (name, value) in headers {
+      match *name {
+        "transfer-encoding" => framing.add_transfer_encoding(value.as_bytes()),
+        "content-length" => framing.add_content_length(value.as_bytes()),
+        _ => unreachable!(),
+      }
+    }
+
+    framing.body()
+  }
+
+  #[test]
+  fn no_framing_headers_means_empty_body() {
+    assert_eq!(framing(&[]), Ok(Some(0)));
+  }
+
+  #[test]
+  fn content_length() {
+    assert_eq!(framing(&[("content-length", "5")]), Ok(Some(5)));
+    assert_eq!(framing(&[("content-length", " 5 ")]), Ok(Some(5)));
+  }
+
+  #[test]
+  fn chunked() {
+    assert_eq!(framing(&[("transfer-encoding", "chunked")]), Ok(None));
+    assert_eq!(framing(&[("transfer-encoding", "Chunked")]), Ok(None));
+  }
+
+  #[test]
+  fn transfer_encoding_with_content_length_is_rejected() {
+    let headers = [("transfer-encoding", "chunked"), ("content-length", "5")];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+
+    let headers = [("content-length", "5"), ("transfer-encoding", "chunked")];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+  }
+
+  #[test]
+  fn unsupported_coding_is_not_implemented() {
+    let headers = [("transfer-encoding", "gzip, chunked")];
+    assert_eq!(framing(&headers), Err(StatusCode::NOT_IMPLEMENTED));
+
+    let headers = [
+      ("transfer-encoding", "gzip"),
+      ("transfer-encoding", "chunked"),
+    ];
+    assert_eq!(framing(&headers), Err(StatusCode::NOT_IMPLEMENTED));
+  }
+
+  #[test]
+  fn chunked_has_to_be_last_and_only_once() {
+    let headers = [("transfer-encoding", "chunked, chunked")];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+
+    let headers = [
+      ("transfer-encoding", "chunked"),
+      ("transfer-encoding", "chunked"),
+    ];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+
+    let headers = [("transfer-encoding", "chunked, gzip")];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+  }
+
+  #[test]
+  fn empty_transfer_encoding_is_rejected() {
+    assert_eq!(
+      framing(&[("transfer-encoding", "")]),
+      Err(StatusCode::BAD_REQUEST)
+    );
+    assert_eq!(
+      framing(&[("transfer-encoding", " , ")]),
+      Err(StatusCode::BAD_REQUEST)
+    );
+  }
+
+  #[test]
+  fn duplicate_content_length_has_to_match() {
+    assert_eq!(framing(&[("content-length", "5, 5")]), Ok(Some(5)));
+
+    let headers = [("content-length", "5"), ("content-length", "5")];
+    assert_eq!(framing(&headers), Ok(Some(5)));
+
+    let headers = [("content-length", "5, 6")];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+
+    let headers = [("content-length", "5"), ("content-length", "6")];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+  }
+
+  #[test]
+  fn invalid_content_length_is_rejected() {
+    for value in &["", "-1", "+5", "5a", "0x10", "5 5", "1.0"] {
+      let headers = [("content-length", *value)];
+      assert_eq!(
+        framing(&headers),
+        Err(StatusCode::BAD_REQUEST),
+        "{:?}",
+        value
+      );
+    }
+  }
+
+  #[test]
+  fn overflowing_content_length_is_rejected() {
+    let max = usize::MAX.to_string();
+    assert_eq!(framing(&[("content-length", &max)]), Ok(Some(usize::MAX)));
+
+    let overflow = format!("{}0", max);
+    let headers = [("content-length", overflow.as_str())];
+    assert_eq!(framing(&headers), Err(StatusCode::BAD_REQUEST));
+  }
+}
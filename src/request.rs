@@ -1,15 +1,25 @@
 use super::*;
 
+use futures::sync::oneshot;
+use std::net::SocketAddr;
+
 pub struct Request {
   pub data: BytesMut,
   pub(crate) on_data: OnData,
+  pub(crate) on_upgrade: Option<oneshot::Sender<upgrade::Upgraded>>,
   pub(crate) is_last: bool,
   pub(crate) has_function: bool,
   pub(crate) uri: Uri,
-  method: String,
+  pub(crate) trailers: chunk::Trailers,
+  pub(crate) peer_addr: Option<SocketAddr>,
+  pub(crate) local_addr: Option<SocketAddr>,
+  pub(crate) peer_cred: Option<transport::PeerCred>,
+  pub(crate) tls: Option<transport::TlsInfo>,
+  pub(crate) method: String,
   version: u8,
   request_data: BytesMut,
-  headers: hashbrown::HashMap<String, Vec<u8>>,
+  extensions: Extensions,
+  pub(crate) headers: hashbrown::HashMap<String, Vec<u8>>,
 }
 
 impl Request {
@@ -19,10 +29,17 @@ impl Request {
       is_last: false,
       version: 0,
       uri: Uri::default(),
+      trailers: hashbrown::HashMap::new(),
+      peer_addr: None,
+      local_addr: None,
+      peer_cred: None,
+      tls: None,
       on_data: OnData::Empty,
+      on_upgrade: None,
       method: String::new(),
       headers: hashbrown::HashMap::new(),
       request_data: BytesMut::new(),
+      extensions: Extensions::new(),
       data: BytesMut::new(),
     }
   }
@@ -35,10 +52,84 @@ impl Request {
     self.on_data = OnData::Function(Box::new(func));
   }
 
+  // connection is handed over once `Response::upgrade` is sent and handler completes
+  pub fn on_upgrade(&mut self) -> upgrade::OnUpgrade {
+    let (sender, receiver) = oneshot::channel();
+    self.on_upgrade = Some(sender);
+    upgrade::OnUpgrade::new(receiver)
+  }
+
+  // protocol requested by client with Upgrade header
+  pub fn upgrade(&self) -> Option<&str> {
+    let connection = self.headers.get("connection")?;
+    if self.version == 0 || !response::has_token(connection, b"upgrade") {
+      return None;
+    }
+
+    self
+      .headers
+      .get("upgrade")
+      .and_then(|value| std::str::from_utf8(value).ok())
+  }
+
   pub fn is_last(&self) -> bool {
     self.is_last
   }
 
+  // trailer fields of chunked body, available only once the last chunk was received
+  pub fn trailers(&self) -> Option<&chunk::Trailers> {
+    if self.is_last {
+      Some(&self.trailers)
+    } else {
+      None
+    }
+  }
+
+  // id of the last server-sent event received by reconnecting client
+  pub fn last_event_id(&self) -> Option<&str> {
+    self
+      .headers
+      .get("last-event-id")
+      .and_then(|value| std::str::from_utf8(value).ok())
+  }
+
+  pub fn peer_addr(&self) -> Option<SocketAddr> {
+    self.peer_addr
+  }
+
+  pub fn local_addr(&self) -> Option<SocketAddr> {
+    self.local_addr
+  }
+
+  // uid, gid and pid of the client connected over unix socket
+  pub fn peer_cred(&self) -> Option<transport::PeerCred> {
+    self.peer_cred
+  }
+
+  // server name sent by the client in tls handshake
+  pub fn sni(&self) -> Option<&str> {
+    self.tls.as_ref().and_then(|tls| tls.sni.as_deref())
+  }
+
+  // negotiated tls protocol version, None for plain connections
+  pub fn tls_version(&self) -> Option<&str> {
+    self.tls.as_ref().and_then(|tls| tls.version)
+  }
+
+  // client certificate verified against configured CA bundle
+  pub fn client_cert(&self) -> Option<&transport::ClientCert> {
+    self.tls.as_ref().and_then(|tls| tls.client_cert.as_ref())
+  }
+
+  // type keyed storage to pass values (user, request id, etc) from middleware to handlers
+  pub fn extensions(&self) -> &Extensions {
+    &self.extensions
+  }
+
+  pub fn extensions_mut(&mut self) -> &mut Extensions {
+    &mut self.extensions
+  }
+
   pub fn data(&mut self) -> &mut BytesMut {
     &mut self.data
   }
@@ -49,6 +140,9 @@ impl Request {
     self.method = method;
     self.version = version;
     self.is_last = false;
+    self.extensions.clear();
+    self.trailers.clear();
+    self.on_upgrade = None;
     self.request_data = request_data;
   }
 
@@ -64,3 +158,9 @@ impl Request {
     }
   }
 }
+
+impl Default for Request {
+  fn default() -> Request {
+    Request::new()
+  }
+}
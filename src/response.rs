@@ -1,51 +1,208 @@
 use super::*;
 
+use http::header::{HeaderName, HeaderValue, CACHE_CONTROL, CONNECTION, CONTENT_TYPE};
+use http::StatusCode;
+use std::mem;
+
+// status lines for all standard codes are generated once per thread
+thread_local!(static STATUS_LINES: Vec<Vec<u8>> = (100..600).map(generate_status_line).collect());
+
 pub struct Response {
-  pub(crate) socket: WriteHalf,
-  status: &'static str,
+  pub(crate) writer: Box<dyn writer::ResponseWriter>,
+  status: StatusCode,
+  headers: HeaderMap,
+  // connection state of the current request, set by reader
+  pub(crate) version: u8,
+  pub(crate) keep_alive: bool,
+  pub(crate) started: bool,
+  pub(crate) upgraded: bool,
+  pub(crate) write_timeout: std::time::Duration,
 }
 
 impl Response {
-  pub fn new(socket: WriteHalf) -> Response {
+  pub fn new<W>(socket: W) -> Response
+  where
+    W: AsyncWrite + Send + Sync + 'static,
+  {
+    Response::with_writer(Box::new(writer::Http1Writer::new(Box::new(socket))))
+  }
+
+  pub(crate) fn with_writer(writer: Box<dyn writer::ResponseWriter>) -> Response {
     Response {
-      socket,
-      status: "200 Ok",
+      writer,
+      status: StatusCode::OK,
+      headers: HeaderMap::new(),
+      version: 1,
+      keep_alive: true,
+      started: false,
+      upgraded: false,
+      write_timeout: std::time::Duration::from_secs(30),
     }
   }
 
-  pub fn status(&mut self, status: &'static str) {
+  pub fn status(&mut self, status: StatusCode) {
     self.status = status;
   }
 
-  pub fn write(self, body: &[u8]) -> writer::WriteAll {
-    let mut buf = BytesMut::with_capacity(4096);
+  // close connection once this response is sent
+  pub fn close(&mut self) {
+    self.keep_alive = false;
+  }
+
+  // HeaderValue rejects CR, LF and other control characters which prevents header injection
+  pub fn header(&mut self, name: &str, value: &str) -> Result<(), std::io::Error> {
+    let name = HeaderName::from_bytes(name.as_bytes())
+      .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid header name"))?;
+    let value = HeaderValue::from_bytes(value.as_bytes())
+      .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid header value"))?;
+
+    self.headers.append(name, value);
+    Ok(())
+  }
+
+  pub fn headers_mut(&mut self) -> &mut HeaderMap {
+    &mut self.headers
+  }
+
+  pub fn write<B: Into<Bytes>>(mut self, body: B) -> writer::WriteAll {
+    let body = body.into();
+    let head = self.head(Some(body.len()));
+
+    self.writer.head(head);
+    self.writer.data(body, true);
+    writer::write_all(self)
+  }
+
+  // start streaming response, body is sent with `write_chunk` and completed with `finish`
+  pub fn write_head(mut self) -> writer::WriteAll {
+    let head = self.head(None);
+
+    self.writer.head(head);
+    writer::write_all(self)
+  }
+
+  pub fn write_chunk<B: Into<Bytes>>(mut self, data: B) -> writer::WriteAll {
+    self.writer.data(data.into(), false);
+    writer::write_all(self)
+  }
+
+  pub fn finish(mut self) -> writer::WriteAll {
+    self.writer.data(Bytes::new(), true);
+    writer::write_all(self)
+  }
+
+  // drive body from the stream, content_length None sends it with chunked framing
+  pub fn write_stream<S>(
+    mut self,
+    stream: S,
+    content_length: Option<usize>,
+  ) -> writer::WriteStream<S>
+  where
+    S: Stream<Item = Bytes, Error = std::io::Error>,
+  {
+    let head = self.head(content_length);
+
+    self.writer.head(head);
+    writer::write_stream(self, stream, content_length)
+  }
+
+  // accept Upgrade request with 101 response, connection is passed to `Request::on_upgrade`
+  // once the returned future and handler complete
+  pub fn upgrade(mut self, protocol: &str) -> Result<writer::WriteAll, std::io::Error> {
+    self.header("upgrade", protocol)?;
+    self.status = StatusCode::SWITCHING_PROTOCOLS;
+
+    Ok(self.write_head())
+  }
+
+  // server-sent events with heartbeat comment sent after each idle `heartbeat` interval
+  pub fn write_sse<S>(
+    mut self,
+    events: S,
+    heartbeat: std::time::Duration,
+  ) -> writer::WriteStream<sse::EventStream<S>>
+  where
+    S: Stream<Item = sse::Event, Error = std::io::Error>,
+  {
+    self
+      .headers
+      .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    self
+      .headers
+      .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
 
-    push(&mut buf, b"HTTP/1.1 ");
-    push(&mut buf, self.status.as_bytes());
-    push(&mut buf, b"\r\n");
+    self.write_stream(sse::EventStream::new(events, heartbeat), None)
+  }
+
+  // content_length None means body is streamed, it is delimited by closing the
+  // connection for HTTP/1.0 clients
+  fn head(&mut self, content_length: Option<usize>) -> writer::Head {
+    self.started = true;
 
-    date::set_date_header(&mut buf);
+    for value in self.headers.get_all(CONNECTION).iter() {
+      if has_token(value.as_bytes(), b"close") {
+        self.keep_alive = false;
+      }
+    }
 
-    let body_len = body.len();
+    // status and headers are set per response, socket is reused for next request
+    let status = mem::replace(&mut self.status, StatusCode::OK);
+    let headers = mem::replace(&mut self.headers, HeaderMap::new());
 
-    if body_len > 0 {
-      push(&mut buf, b"content-length: ");
-      push(&mut buf, body_len.to_string().as_bytes());
-      push(&mut buf, b"\r\n\r\n");
-      push(&mut buf, body);
-    } else {
-      push(&mut buf, b"content-length: 0\r\n\r\n")
+    if status == StatusCode::SWITCHING_PROTOCOLS {
+      self.upgraded = true;
+    } else if content_length.is_none() && self.version == 0 {
+      self.keep_alive = false;
     }
 
-    writer::write_all(self, buf)
+    writer::Head {
+      status,
+      headers,
+      content_length,
+      version: self.version,
+      keep_alive: self.keep_alive,
+    }
   }
+}
 
-  pub(crate) fn shutdown(&mut self) {
-    // TODO: handle unwrap
-    self.socket.shutdown().unwrap();
+pub(crate) fn set_status_line(buf: &mut BytesMut, status: StatusCode) {
+  match status.as_u16() {
+    code @ 100..=599 => STATUS_LINES.with(|lines| push(buf, &lines[code as usize - 100])),
+    code => push(buf, &generate_status_line(code)),
   }
 }
 
+fn generate_status_line(code: u16) -> Vec<u8> {
+  // every u16 in 100..1000 is a valid status code
+  let status = StatusCode::from_u16(code).expect("status code must be in 100..1000");
+  format!(
+    "HTTP/1.1 {} {}\r\n",
+    status.as_str(),
+    status.canonical_reason().unwrap_or("")
+  )
+  .into_bytes()
+}
+
+// check comma separated header value for a token (case insensitive)
+pub(crate) fn has_token(value: &[u8], token: &[u8]) -> bool {
+  value
+    .split(|&b| b == b',')
+    .any(|part| trim(part).eq_ignore_ascii_case(token))
+}
+
+pub(crate) fn trim(value: &[u8]) -> &[u8] {
+  let start = value
+    .iter()
+    .position(|&b| b != b' ' && b != b'\t')
+    .unwrap_or(value.len());
+  let end = value
+    .iter()
+    .rposition(|&b| b != b' ' && b != b'\t')
+    .map_or(start, |pos| pos + 1);
+
+  &value[start..end]
+}
+
 // fast unsafe push
 pub(crate) fn push(buf: &mut BytesMut, data: &[u8]) {
   if buf.remaining_mut() < data.len() {
@@ -57,3 +214,49 @@ pub(crate) fn push(buf: &mut BytesMut, data: &[u8]) {
     buf.advance_mut(data.len());
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn response() -> Response {
+    Response::new(std::io::sink())
+  }
+
+  #[test]
+  fn header_values_with_line_breaks_are_rejected() {
+    let mut res = response();
+
+    for value in &["a\r\nset-cookie: x=1", "a\nb", "a\rb", "a\0b"] {
+      let err = res.header("x-test", value).unwrap_err();
+      assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{:?}", value);
+    }
+    assert!(res.headers_mut().is_empty());
+  }
+
+  #[test]
+  fn invalid_header_names_are_rejected() {
+    let mut res = response();
+
+    for name in &["", "bad name", "x-test:", "x\r\ny", "ä"] {
+      let err = res.header(name, "1").unwrap_err();
+      assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{:?}", name);
+    }
+    assert!(res.headers_mut().is_empty());
+  }
+
+  #[test]
+  fn repeated_headers_are_kept() {
+    let mut res = response();
+    res.header("Set-Cookie", "a=1").unwrap();
+    res.header("set-cookie", "b=2").unwrap();
+    res
+      .header("content-type", "text/plain; charset=utf-8")
+      .unwrap();
+
+    let headers = res.headers_mut();
+    let cookies: Vec<_> = headers.get_all("set-cookie").iter().collect();
+    assert_eq!(cookies, ["a=1", "b=2"]);
+    assert_eq!(headers["content-type"], "text/plain; charset=utf-8");
+  }
+}
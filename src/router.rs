@@ -13,7 +13,8 @@ impl Router {
     }
   }
 
-  pub fn add<F>(&mut self, method: &str, path: &'static str, func: F)
+  // routes are not matched by method yet
+  pub fn add<F>(&mut self, _method: &str, path: &'static str, func: F)
   where
     F: Fn(ReqResTuple) -> ReturnFuture + Send + Sync + 'static,
   {
@@ -32,6 +33,26 @@ impl Router {
       }
     };
   }
+
+  // websocket endpoint, `func` gets each connection once handshake response is sent
+  #[cfg(feature = "websocket")]
+  pub fn websocket<F, R>(&mut self, path: &'static str, config: websocket::Config, func: F)
+  where
+    F: Fn(websocket::WebSocket) -> R + Send + Sync + 'static,
+    R: Future<Item = (), Error = ()> + Send + 'static,
+  {
+    let func = std::sync::Arc::new(func);
+    self.add("GET", path, move |(req, res)| {
+      let func = func.clone();
+      websocket::accept(req, res, config, move |socket| func(socket))
+    });
+  }
+}
+
+impl Default for Router {
+  fn default() -> Router {
+    Router::new()
+  }
 }
 
 impl RouterSearch for Router {
@@ -44,7 +65,7 @@ impl RouterSearch for Router {
       // handle uri
       return match node.method.as_ref() {
         Some(v) => (v)((req, res)),
-        None => Box::new(res.write("".as_bytes()).map(|res| ((req, res)))),
+        None => Box::new(res.write("".as_bytes()).map(|res| (req, res))),
       };
     }
 
@@ -52,20 +73,20 @@ impl RouterSearch for Router {
     for seg in req.uri.path().split('/') {
       if !seg.is_empty() {
         if node.children.is_empty() {
-          return Box::new(res.write("".as_bytes()).map(|res| ((req, res))));
+          return Box::new(res.write("".as_bytes()).map(|res| (req, res)));
         }
 
         node = match node.children.get(seg) {
           Some(v) => v,
-          None => return Box::new(res.write("".as_bytes()).map(|res| ((req, res)))),
+          None => return Box::new(res.write("".as_bytes()).map(|res| (req, res))),
         }
       }
     }
 
-    return match node.method.as_ref() {
+    match node.method.as_ref() {
       Some(v) => (v)((req, res)),
-      None => Box::new(res.write("".as_bytes()).map(|res| ((req, res)))),
-    };
+      None => Box::new(res.write("".as_bytes()).map(|res| (req, res))),
+    }
   }
 }
 
@@ -98,6 +119,6 @@ impl Node {
       );
     }
 
-    return self.children.get_mut(seg).unwrap();
+    self.children.get_mut(seg).unwrap()
   }
 }
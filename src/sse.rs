@@ -0,0 +1,172 @@
+use super::*;
+
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+// comment line which keeps idle connections open through proxies
+const HEARTBEAT: &[u8] = b":\n\n";
+
+pub struct Event {
+  event: Option<String>,
+  id: Option<String>,
+  retry: Option<u64>,
+  data: String,
+}
+
+impl Event {
+  pub fn new(data: &str) -> Event {
+    Event {
+      event: None,
+      id: None,
+      retry: None,
+      data: data.to_string(),
+    }
+  }
+
+  pub fn event(mut self, event: &str) -> Event {
+    self.event = Some(strip_newlines(event));
+    self
+  }
+
+  pub fn id(mut self, id: &str) -> Event {
+    self.id = Some(strip_newlines(id));
+    self
+  }
+
+  // reconnection time in milliseconds
+  pub fn retry(mut self, retry: u64) -> Event {
+    self.retry = Some(retry);
+    self
+  }
+
+  fn encode(&self, buf: &mut BytesMut) {
+    if let Some(event) = &self.event {
+      set_field(buf, b"event", event);
+    }
+
+    if let Some(id) = &self.id {
+      set_field(buf, b"id", id);
+    }
+
+    if let Some(retry) = self.retry {
+      set_field(buf, b"retry", &retry.to_string());
+    }
+
+    // multi line data has to be sent as separate data fields, CR alone ends a line
+    // too and trailing empty line is kept
+    for line in self
+      .data
+      .split("\r\n")
+      .flat_map(|line| line.split(['\r', '\n']))
+    {
+      set_field(buf, b"data", line);
+    }
+
+    response::push(buf, b"\n");
+  }
+}
+
+// converts events in to body bytes and sends heartbeat when stream is idle
+pub struct EventStream<S> {
+  events: S,
+  heartbeat: Duration,
+  timer: Delay,
+}
+
+impl<S> EventStream<S> {
+  pub fn new(events: S, heartbeat: Duration) -> EventStream<S> {
+    EventStream {
+      events,
+      heartbeat,
+      timer: Delay::new(Instant::now() + heartbeat),
+    }
+  }
+}
+
+impl<S> Stream for EventStream<S>
+where
+  S: Stream<Item = Event, Error = std::io::Error>,
+{
+  type Item = Bytes;
+  type Error = std::io::Error;
+
+  fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+    match self.events.poll()? {
+      Async::Ready(Some(event)) => {
+        let mut buf = BytesMut::with_capacity(event.data.len() + 64);
+        event.encode(&mut buf);
+
+        self.timer.reset(Instant::now() + self.heartbeat);
+        return Ok(Async::Ready(Some(buf.freeze())));
+      }
+      Async::Ready(None) => return Ok(Async::Ready(None)),
+      Async::NotReady => {}
+    }
+
+    match self.timer.poll().map_err(std::io::Error::other)? {
+      Async::Ready(_) => {
+        self.timer.reset(Instant::now() + self.heartbeat);
+        Ok(Async::Ready(Some(Bytes::from_static(HEARTBEAT))))
+      }
+      Async::NotReady => Ok(Async::NotReady),
+    }
+  }
+}
+
+fn set_field(buf: &mut BytesMut, name: &[u8], value: &str) {
+  response::push(buf, name);
+  response::push(buf, b": ");
+  response::push(buf, value.as_bytes());
+  response::push(buf, b"\n");
+}
+
+fn strip_newlines(value: &str) -> String {
+  value.replace(['\r', '\n'], "")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn encode(event: Event) -> String {
+    let mut buf = BytesMut::new();
+    event.encode(&mut buf);
+    String::from_utf8(buf.to_vec()).unwrap()
+  }
+
+  #[test]
+  fn data_is_split_on_every_line_ending() {
+    assert_eq!(
+      encode(Event::new("a\nb\r\nc\rd")),
+      "data: a\ndata: b\ndata: c\ndata: d\n\n"
+    );
+  }
+
+  #[test]
+  fn cr_does_not_inject_fields() {
+    assert_eq!(encode(Event::new("x\rid: 1")), "data: x\ndata: id: 1\n\n");
+    assert_eq!(
+      encode(Event::new("x").id("1\rretry: 0")),
+      "id: 1retry: 0\ndata: x\n\n"
+    );
+  }
+
+  #[test]
+  fn empty_lines_are_kept() {
+    assert_eq!(encode(Event::new("")), "data: \n\n");
+    assert_eq!(encode(Event::new("a\n")), "data: a\ndata: \n\n");
+    assert_eq!(
+      encode(Event::new("a\r\n\r\nb")),
+      "data: a\ndata: \ndata: b\n\n"
+    );
+  }
+
+  #[test]
+  fn fields_order() {
+    let event = Event::new("x").event("update").id("7").retry(10);
+    assert_eq!(
+      encode(event),
+      "event: update\nid: 7\nretry: 10\ndata: x\n\n"
+    );
+  }
+}
@@ -0,0 +1,826 @@
+use super::*;
+
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{
+  AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+  ProtocolVersion, ResolvesServerCert, RootCertStore, ServerConfig, ServerSession, Session,
+  SignatureScheme,
+};
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Buf;
+use iovec::IoVec;
+
+// encrypted records waiting for the socket, plaintext writes past it wait for flush
+const SEND_BUFFER_LIMIT: usize = 64 * 1024;
+
+// wraps accepted sockets in tls sessions
+#[derive(Clone)]
+pub struct Acceptor {
+  config: Arc<ServerConfig>,
+}
+
+impl Acceptor {
+  pub fn new(config: Arc<ServerConfig>) -> Acceptor {
+    Acceptor { config }
+  }
+
+  // certificate chain and private key (pkcs8 or rsa) in PEM format, ALPN advertises http/1.1
+  pub fn from_pem<P: AsRef<Path>>(cert: P, key: P) -> Result<Acceptor, io::Error> {
+    Builder::new().cert(cert, key)?.build()
+  }
+
+  pub fn accept<S>(&self, socket: S) -> Accept<S>
+  where
+    S: AsyncRead + AsyncWrite,
+  {
+    let mut session = ServerSession::new(&self.config);
+    session.set_buffer_limit(SEND_BUFFER_LIMIT);
+
+    Accept {
+      stream: Some(TlsStream {
+        io: socket,
+        session,
+        eof: false,
+      }),
+    }
+  }
+}
+
+// acceptor with certificates selected by SNI hostname and optional client verification
+pub struct Builder {
+  resolver: Resolver,
+  client_roots: Option<(RootCertStore, bool)>,
+  protocols: Vec<Vec<u8>>,
+}
+
+impl Builder {
+  pub fn new() -> Builder {
+    Builder {
+      resolver: Resolver {
+        default: None,
+        by_name: hashbrown::HashMap::new(),
+      },
+      client_roots: None,
+      protocols: vec![b"http/1.1".to_vec()],
+    }
+  }
+
+  // certificate for clients without SNI or with unknown hostname
+  pub fn cert<P: AsRef<Path>>(mut self, cert: P, key: P) -> Result<Builder, io::Error> {
+    let certified = load_certified_key(cert, key)?;
+    certified
+      .cross_check_end_entity_cert(None)
+      .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+    self.resolver.default = Some(certified);
+    Ok(self)
+  }
+
+  // certificate for a single SNI hostname, it has to be valid for that name
+  pub fn sni_cert<P: AsRef<Path>>(
+    mut self,
+    hostname: &str,
+    cert: P,
+    key: P,
+  ) -> Result<Builder, io::Error> {
+    let name = webpki::DNSNameRef::try_from_ascii_str(hostname)
+      .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid hostname"))?;
+
+    let certified = load_certified_key(cert, key)?;
+    certified
+      .cross_check_end_entity_cert(Some(name))
+      .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+    self
+      .resolver
+      .by_name
+      .insert(hostname.to_lowercase(), certified);
+    Ok(self)
+  }
+
+  // verify client certificates against CA bundle in PEM format,
+  // `required` rejects clients which do not send a certificate
+  pub fn client_ca<P: AsRef<Path>>(mut self, ca: P, required: bool) -> Result<Builder, io::Error> {
+    let mut roots = RootCertStore::empty();
+    match roots.add_pem_file(&mut open(ca)?) {
+      Ok((added, _)) if added > 0 => {}
+      _ => return Err(error("No valid CA certificate found")),
+    }
+
+    self.client_roots = Some((roots, required));
+    Ok(self)
+  }
+
+  // ALPN protocols in preference order, `["h2", "http/1.1"]` for connections
+  // served with `http2::serve`
+  pub fn protocols(mut self, protocols: &[&str]) -> Builder {
+    self.protocols = protocols
+      .iter()
+      .map(|protocol| protocol.as_bytes().to_vec())
+      .collect();
+    self
+  }
+
+  pub fn build(self) -> Result<Acceptor, io::Error> {
+    if self.resolver.default.is_none() && self.resolver.by_name.is_empty() {
+      return Err(io::Error::new(
+        ErrorKind::InvalidInput,
+        "No certificate configured",
+      ));
+    }
+
+    let verifier = match self.client_roots {
+      Some((roots, true)) => AllowAnyAuthenticatedClient::new(roots),
+      Some((roots, false)) => AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+      None => NoClientAuth::new(),
+    };
+
+    let mut config = ServerConfig::new(verifier);
+    config.cert_resolver = Arc::new(self.resolver);
+    config.set_protocols(&self.protocols);
+
+    Ok(Acceptor::new(Arc::new(config)))
+  }
+}
+
+impl Default for Builder {
+  fn default() -> Builder {
+    Builder::new()
+  }
+}
+
+struct Resolver {
+  default: Option<CertifiedKey>,
+  by_name: hashbrown::HashMap<String, CertifiedKey>,
+}
+
+impl ResolvesServerCert for Resolver {
+  fn resolve(
+    &self,
+    server_name: Option<webpki::DNSNameRef>,
+    _sigschemes: &[SignatureScheme],
+  ) -> Option<CertifiedKey> {
+    server_name
+      .and_then(|name| {
+        let name: &str = name.into();
+        self.by_name.get(&name.to_lowercase())
+      })
+      .or(self.default.as_ref())
+      .cloned()
+  }
+}
+
+fn open<P: AsRef<Path>>(path: P) -> Result<BufReader<File>, io::Error> {
+  Ok(BufReader::new(File::open(path)?))
+}
+
+fn load_certified_key<P: AsRef<Path>>(cert: P, key: P) -> Result<CertifiedKey, io::Error> {
+  let certs = pemfile::certs(&mut open(cert)?).map_err(|_| error("Invalid certificate"))?;
+  if certs.is_empty() {
+    return Err(error("No certificate found"));
+  }
+
+  let key =
+    sign::any_supported_type(&load_key(key)?).map_err(|_| error("Unsupported private key type"))?;
+
+  Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+fn load_key<P: AsRef<Path>>(path: P) -> Result<rustls::PrivateKey, io::Error> {
+  let mut keys = pemfile::pkcs8_private_keys(&mut open(&path)?).unwrap_or_default();
+  if keys.is_empty() {
+    keys = pemfile::rsa_private_keys(&mut open(&path)?).unwrap_or_default();
+  }
+
+  keys.pop().ok_or_else(|| error("No private key found"))
+}
+
+// socket which is not ready yet maps to NotReady, task is already registered for wake up
+fn would_block<T>(result: Result<T, io::Error>) -> Poll<T, io::Error> {
+  match result {
+    Ok(value) => Ok(Async::Ready(value)),
+    Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(Async::NotReady),
+    Err(e) => Err(e),
+  }
+}
+
+fn error(message: &'static str) -> io::Error {
+  io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// resolves to the stream once tls handshake is complete
+pub struct Accept<S> {
+  stream: Option<TlsStream<S>>,
+}
+
+impl<S> Future for Accept<S>
+where
+  S: AsyncRead + AsyncWrite,
+{
+  type Item = TlsStream<S>;
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    {
+      let stream = self
+        .stream
+        .as_mut()
+        .expect("Accept polled after completion");
+
+      while stream.session.is_handshaking() || stream.session.wants_write() {
+        futures::try_ready!(would_block(stream.write_tls()));
+
+        if stream.session.is_handshaking()
+          && !stream.session.wants_write()
+          && futures::try_ready!(would_block(stream.read_tls())) == 0
+        {
+          return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "Connection closed during tls handshake",
+          ));
+        }
+      }
+    }
+
+    Ok(Async::Ready(self.stream.take().unwrap()))
+  }
+}
+
+pub struct TlsStream<S> {
+  io: S,
+  session: ServerSession,
+  eof: bool,
+}
+
+impl<S> TlsStream<S> {
+  pub fn get_ref(&self) -> (&S, &ServerSession) {
+    (&self.io, &self.session)
+  }
+
+  // receive and decrypt records from the socket, 0 means socket is closed
+  fn read_tls(&mut self) -> Result<usize, io::Error>
+  where
+    S: AsyncRead + AsyncWrite,
+  {
+    let amt = self.session.read_tls(&mut self.io)?;
+    if let Err(e) = self.session.process_new_packets() {
+      // try to let the client know about the failure
+      let _ = self.session.write_tls(&mut self.io);
+      return Err(io::Error::new(ErrorKind::InvalidData, e));
+    }
+
+    Ok(amt)
+  }
+
+  // push all pending encrypted records to the socket, together in one vectored write
+  fn write_tls(&mut self) -> Result<(), io::Error>
+  where
+    S: AsyncWrite,
+  {
+    while self.session.wants_write() {
+      self.session.writev_tls(&mut Vectored(&mut self.io))?;
+    }
+
+    Ok(())
+  }
+}
+
+impl<S> Read for TlsStream<S>
+where
+  S: AsyncRead + AsyncWrite,
+{
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    // records left from previous writes are flushed as reader is the one woken up
+    match self.write_tls() {
+      Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+      result => result?,
+    }
+
+    loop {
+      let amt = match self.session.read(buf) {
+        // client sent close_notify, connection is closed cleanly
+        Err(ref e) if e.kind() == ErrorKind::ConnectionAborted => return Ok(0),
+        result => result?,
+      };
+
+      if amt > 0 || self.eof || buf.is_empty() {
+        return Ok(amt);
+      }
+
+      if self.read_tls()? == 0 {
+        self.eof = true;
+      }
+    }
+  }
+}
+
+// plaintext is encrypted into the session and records go out on flush, so response
+// head and body are sent together instead of one record and segment per write
+impl<S> Write for TlsStream<S>
+where
+  S: AsyncWrite,
+{
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    loop {
+      let amt = self.session.write(buf)?;
+      if amt > 0 || buf.is_empty() {
+        return Ok(amt);
+      }
+
+      // session buffer is full, records have to leave before more data is accepted
+      self.write_tls()?;
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.session.flush()?;
+    self.write_tls()?;
+    self.io.flush()
+  }
+}
+
+impl<S> AsyncRead for TlsStream<S> where S: AsyncRead + AsyncWrite {}
+
+impl<S> AsyncWrite for TlsStream<S>
+where
+  S: AsyncRead + AsyncWrite,
+{
+  fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+    let mut amt = 0;
+    while buf.has_remaining() {
+      let written = match self.write(buf.bytes()) {
+        Ok(written) => written,
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock && amt > 0 => break,
+        Err(e) => return would_block(Err(e)),
+      };
+
+      buf.advance(written);
+      amt += written;
+    }
+
+    Ok(Async::Ready(amt))
+  }
+
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    self.session.send_close_notify();
+    futures::try_ready!(would_block(self.write_tls()));
+    self.io.shutdown()
+  }
+}
+
+// lets rustls hand all pending records to the socket at once
+struct Vectored<'a, S>(&'a mut S);
+
+impl<'a, S: AsyncWrite> rustls::WriteV for Vectored<'a, S> {
+  fn writev(&mut self, vbytes: &[&[u8]]) -> io::Result<usize> {
+    match self.0.write_buf(&mut Slices::new(vbytes))? {
+      Async::Ready(amt) => Ok(amt),
+      Async::NotReady => Err(ErrorKind::WouldBlock.into()),
+    }
+  }
+}
+
+// records as one buffer, `first` is the unwritten part of the current record
+struct Slices<'a> {
+  first: &'a [u8],
+  rest: &'a [&'a [u8]],
+}
+
+impl<'a> Slices<'a> {
+  fn new(slices: &'a [&'a [u8]]) -> Slices<'a> {
+    let mut buf = Slices {
+      first: &[],
+      rest: slices,
+    };
+    buf.advance(0);
+    buf
+  }
+}
+
+impl<'a> Buf for Slices<'a> {
+  fn remaining(&self) -> usize {
+    self.first.len() + self.rest.iter().map(|slice| slice.len()).sum::<usize>()
+  }
+
+  fn bytes(&self) -> &[u8] {
+    self.first
+  }
+
+  fn advance(&mut self, mut cnt: usize) {
+    loop {
+      let amt = std::cmp::min(cnt, self.first.len());
+      self.first = &self.first[amt..];
+      cnt -= amt;
+
+      // move on to the next record, skipping empty ones
+      if !self.first.is_empty() || self.rest.is_empty() {
+        return;
+      }
+      self.first = self.rest[0];
+      self.rest = &self.rest[1..];
+    }
+  }
+
+  fn bytes_vec<'b>(&'b self, dst: &mut [&'b IoVec]) -> usize {
+    let slices = std::iter::once(self.first).chain(self.rest.iter().cloned());
+
+    let mut filled = 0;
+    for (dst, slice) in dst.iter_mut().zip(slices.filter(|slice| !slice.is_empty())) {
+      *dst = slice.into();
+      filled += 1;
+    }
+    filled
+  }
+}
+
+impl<S> transport::Transport for TlsStream<S>
+where
+  S: transport::Transport,
+{
+  fn peer_addr(&self) -> Option<SocketAddr> {
+    self.io.peer_addr()
+  }
+
+  fn local_addr(&self) -> Option<SocketAddr> {
+    self.io.local_addr()
+  }
+
+  fn peer_cred(&self) -> Option<transport::PeerCred> {
+    self.io.peer_cred()
+  }
+
+  fn tls_info(&self) -> Option<transport::TlsInfo> {
+    let version = self
+      .session
+      .get_protocol_version()
+      .map(|version| match version {
+        ProtocolVersion::SSLv2 => "SSLv2",
+        ProtocolVersion::SSLv3 => "SSLv3",
+        ProtocolVersion::TLSv1_0 => "TLSv1.0",
+        ProtocolVersion::TLSv1_1 => "TLSv1.1",
+        ProtocolVersion::TLSv1_2 => "TLSv1.2",
+        ProtocolVersion::TLSv1_3 => "TLSv1.3",
+        _ => "unknown",
+      });
+
+    // certificates are only present once verified, end entity comes first
+    let client_cert = self
+      .session
+      .get_peer_certificates()
+      .and_then(|certs| certs.first().and_then(|cert| parse_cert(&cert.0)));
+
+    Some(transport::TlsInfo {
+      sni: self.session.get_sni_hostname().map(String::from),
+      version,
+      alpn: self
+        .session
+        .get_alpn_protocol()
+        .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+      client_cert,
+    })
+  }
+}
+
+// minimal DER reader, certificate is already verified so only the fields
+// exposed to handlers are extracted
+struct Der<'a> {
+  data: &'a [u8],
+}
+
+const BOOLEAN: u8 = 0x01;
+const TBS_VERSION: u8 = 0xa0;
+const TBS_EXTENSIONS: u8 = 0xa3;
+// 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+impl<'a> Der<'a> {
+  fn new(data: &'a [u8]) -> Der<'a> {
+    Der { data }
+  }
+}
+
+impl<'a> Iterator for Der<'a> {
+  // tag and content of the next element
+  type Item = (u8, &'a [u8]);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let (&tag, rest) = self.data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (len, rest) = if first < 0x80 {
+      (first as usize, rest)
+    } else {
+      let size = (first & 0x7f) as usize;
+      if size == 0 || size > 4 || rest.len() < size {
+        return None;
+      }
+
+      let len = rest[..size]
+        .iter()
+        .fold(0, |len, &b| (len << 8) | b as usize);
+      (len, &rest[size..])
+    };
+
+    if rest.len() < len {
+      return None;
+    }
+
+    self.data = &rest[len..];
+    Some((tag, &rest[..len]))
+  }
+}
+
+fn parse_cert(der: &[u8]) -> Option<transport::ClientCert> {
+  let (_, cert) = Der::new(der).next()?;
+  let (_, tbs) = Der::new(cert).next()?;
+  let mut tbs = Der::new(tbs);
+
+  // version is optional, then serial, signature, issuer and validity come before subject
+  let (tag, _) = tbs.next()?;
+  if tag == TBS_VERSION {
+    tbs.next()?;
+  }
+  tbs.nth(2)?;
+
+  let (_, subject) = tbs.next()?;
+  let subject = parse_name(subject)?;
+
+  let mut alt_names = Vec::new();
+  for (tag, value) in tbs {
+    if tag == TBS_EXTENSIONS {
+      alt_names = parse_alt_names(value)?;
+    }
+  }
+
+  Some(transport::ClientCert {
+    subject,
+    alt_names,
+    der: der.to_vec(),
+  })
+}
+
+// RDN sequence as comma separated attributes in certificate order
+fn parse_name(name: &[u8]) -> Option<String> {
+  let mut parts = Vec::new();
+
+  for (_, rdn) in Der::new(name) {
+    for (_, attribute) in Der::new(rdn) {
+      let mut attribute = Der::new(attribute);
+      let (_, oid) = attribute.next()?;
+      let (tag, value) = attribute.next()?;
+
+      let key = match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_string(),
+        oid => format_oid(oid),
+      };
+
+      parts.push(format!(
+        "{}={}",
+        key,
+        escape_value(&decode_string(tag, value))
+      ));
+    }
+  }
+
+  Some(parts.join(","))
+}
+
+// directory string in any of its encodings, invalid characters are replaced
+fn decode_string(tag: u8, value: &[u8]) -> String {
+  match tag {
+    // BMPString, UTF-16 big endian
+    0x1e => {
+      let units: Vec<u16> = value
+        .chunks(2)
+        .map(|unit| match unit {
+          [high, low] => u16::from_be_bytes([*high, *low]),
+          _ => 0xfffd,
+        })
+        .collect();
+      String::from_utf16_lossy(&units)
+    }
+    // UniversalString, UTF-32 big endian
+    0x1c => value
+      .chunks(4)
+      .map(|unit| match unit {
+        [a, b, c, d] => std::char::from_u32(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => None,
+      })
+      .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+      .collect(),
+    // TeletexString, written as latin1 in practice
+    0x14 => value.iter().map(|&b| char::from(b)).collect(),
+    // UTF8String, PrintableString, IA5String
+    _ => String::from_utf8_lossy(value).into_owned(),
+  }
+}
+
+fn parse_alt_names(extensions: &[u8]) -> Option<Vec<transport::AltName>> {
+  let (_, extensions) = Der::new(extensions).next()?;
+
+  for (_, extension) in Der::new(extensions) {
+    let mut extension = Der::new(extension);
+    let (_, oid) = extension.next()?;
+    if oid != SUBJECT_ALT_NAME {
+      continue;
+    }
+
+    // critical flag is optional
+    let (tag, mut value) = extension.next()?;
+    if tag == BOOLEAN {
+      value = extension.next()?.1;
+    }
+
+    let (_, names) = Der::new(value).next()?;
+    let alt_names = Der::new(names)
+      .filter_map(|(tag, value)| {
+        let text = || String::from_utf8_lossy(value).into_owned();
+        match (tag, value.len()) {
+          (0x81, _) => Some(transport::AltName::Email(text())),
+          (0x82, _) => Some(transport::AltName::Dns(text())),
+          (0x86, _) => Some(transport::AltName::Uri(text())),
+          (0x87, 4) => {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(value);
+            Some(transport::AltName::Ip(IpAddr::from(octets)))
+          }
+          (0x87, 16) => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(value);
+            Some(transport::AltName::Ip(IpAddr::from(octets)))
+          }
+          _ => None,
+        }
+      })
+      .collect();
+
+    return Some(alt_names);
+  }
+
+  Some(Vec::new())
+}
+
+fn format_oid(oid: &[u8]) -> String {
+  let mut parts = Vec::new();
+  let mut value: u64 = 0;
+
+  for &b in oid {
+    value = (value << 7) | u64::from(b & 0x7f);
+    if b & 0x80 == 0 {
+      if parts.is_empty() {
+        let first = std::cmp::min(value / 40, 2);
+        parts.push(first.to_string());
+        parts.push((value - first * 40).to_string());
+      } else {
+        parts.push(value.to_string());
+      }
+      value = 0;
+    }
+  }
+
+  parts.join(".")
+}
+
+// special characters of distinguished name values (RFC 4514)
+fn escape_value(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    if let ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' = c {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+
+  escaped
+}
+
+// certificates in tests/certs are self-signed, made with `openssl req -x509` (-x509v1 for v1.der)
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use transport::AltName;
+
+  #[test]
+  fn v1_cert_without_version_field() {
+    let cert = parse_cert(include_bytes!("../tests/certs/v1.der")).unwrap();
+
+    assert_eq!(cert.subject, "CN=v1.example,O=Example");
+    assert!(cert.alt_names.is_empty());
+    assert_eq!(cert.der, &include_bytes!("../tests/certs/v1.der")[..]);
+  }
+
+  #[test]
+  fn cert_with_extensions_but_no_alt_names() {
+    let cert = parse_cert(include_bytes!("../tests/certs/no-san.der")).unwrap();
+
+    assert_eq!(
+      cert.subject,
+      "C=US,ST=CA,L=San Francisco,O=Example\\, Inc.,OU=Web,CN=client,\
+       emailAddress=client@example.com,1.3.6.1.4.1.55555.1=custom"
+    );
+    assert!(cert.alt_names.is_empty());
+  }
+
+  #[test]
+  fn alt_names() {
+    let cert = parse_cert(include_bytes!("../tests/certs/san.der")).unwrap();
+
+    assert_eq!(cert.subject, "CN=san.example");
+    assert_eq!(
+      cert.alt_names,
+      [
+        AltName::Dns("a.example".to_string()),
+        AltName::Dns("b.example".to_string()),
+        AltName::Email("a@example.com".to_string()),
+        AltName::Uri("https://example.com/a".to_string()),
+        AltName::Ip("192.0.2.1".parse().unwrap()),
+        AltName::Ip("2001:db8::1".parse().unwrap()),
+      ]
+    );
+  }
+
+  #[test]
+  fn critical_alt_names() {
+    let cert = parse_cert(include_bytes!("../tests/certs/critical-san.der")).unwrap();
+
+    assert_eq!(
+      cert.alt_names,
+      [
+        AltName::Dns("c.example".to_string()),
+        AltName::Ip("198.51.100.7".parse().unwrap()),
+      ]
+    );
+  }
+
+  #[test]
+  fn bmp_and_teletex_strings() {
+    let cert = parse_cert(include_bytes!("../tests/certs/bmp.der")).unwrap();
+    assert_eq!(cert.subject, "CN=名前,O=Société");
+  }
+
+  #[test]
+  fn universal_string() {
+    // SET { SEQUENCE { 2.5.4.3, UniversalString "a€" } }
+    let name = [
+      0x31, 0x11, 0x30, 0x0f, 0x06, 0x03, 0x55, 0x04, 0x03, 0x1c, 0x08, 0x00, 0x00, 0x00, 0x61,
+      0x00, 0x00, 0x20, 0xac,
+    ];
+    assert_eq!(parse_name(&name).unwrap(), "CN=a€");
+  }
+
+  #[test]
+  fn invalid_strings_are_replaced() {
+    assert_eq!(
+      decode_string(0x1e, &[0x00, 0x61, 0xd8, 0x00, 0x00]),
+      "a\u{fffd}\u{fffd}"
+    );
+    assert_eq!(decode_string(0x1c, &[0x00, 0x11, 0x00, 0x00]), "\u{fffd}");
+    assert_eq!(decode_string(0x0c, &[0x61, 0xff]), "a\u{fffd}");
+  }
+
+  #[test]
+  fn truncated_cert() {
+    let der = include_bytes!("../tests/certs/san.der");
+    for len in 0..der.len() {
+      // inner length is checked against the outer element, nothing may panic
+      let _ = parse_cert(&der[..len]);
+    }
+    assert!(parse_cert(&der[..der.len() - 1]).is_none());
+  }
+
+  #[test]
+  fn long_form_length() {
+    let mut data = vec![0x04, 0x82, 0x01, 0x00];
+    data.extend_from_slice(&[0; 256]);
+    data.extend_from_slice(&[0x05, 0x00]);
+
+    let elements: Vec<_> = Der::new(&data)
+      .map(|(tag, value)| (tag, value.len()))
+      .collect();
+    assert_eq!(elements, [(0x04, 256), (0x05, 0)]);
+    assert_eq!(Der::new(&data[..100]).next(), None);
+    assert_eq!(Der::new(&[0x04, 0x80]).next(), None);
+  }
+
+  #[test]
+  fn oids() {
+    assert_eq!(format_oid(&[0x55, 0x04, 0x03]), "2.5.4.3");
+    assert_eq!(
+      format_oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]),
+      "1.2.840.113549"
+    );
+    assert_eq!(format_oid(&[0x88, 0x37]), "2.999");
+  }
+}
@@ -0,0 +1,286 @@
+use super::*;
+
+use bytes::Buf;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+// any stream the server can run on (tcp, unix socket, tls or in-memory pipe),
+// custom transports only need an empty impl to opt in
+pub trait Transport: AsyncRead + AsyncWrite + Send + 'static {
+  fn peer_addr(&self) -> Option<SocketAddr> {
+    None
+  }
+
+  fn local_addr(&self) -> Option<SocketAddr> {
+    None
+  }
+
+  // credentials of the process on the other end of unix socket
+  fn peer_cred(&self) -> Option<PeerCred> {
+    None
+  }
+
+  // negotiated session of encrypted transports
+  fn tls_info(&self) -> Option<TlsInfo> {
+    None
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerCred {
+  pub uid: u32,
+  pub gid: u32,
+  // not reported on all platforms
+  pub pid: Option<i32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsInfo {
+  pub sni: Option<String>,
+  // "TLSv1.2", "TLSv1.3", etc
+  pub version: Option<&'static str>,
+  // application protocol selected with ALPN, "h2" or "http/1.1"
+  pub alpn: Option<String>,
+  // verified certificate of mutual tls client
+  pub client_cert: Option<ClientCert>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ClientCert {
+  // distinguished name, e.g. "CN=client,O=Example"
+  pub subject: String,
+  pub alt_names: Vec<AltName>,
+  // DER encoded certificate for any further inspection
+  pub der: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AltName {
+  Dns(String),
+  Email(String),
+  Uri(String),
+  Ip(IpAddr),
+}
+
+impl Transport for tokio::net::TcpStream {
+  fn peer_addr(&self) -> Option<SocketAddr> {
+    tokio::net::TcpStream::peer_addr(self).ok()
+  }
+
+  fn local_addr(&self) -> Option<SocketAddr> {
+    tokio::net::TcpStream::local_addr(self).ok()
+  }
+}
+
+// adapter for streams from other crates (in-memory pipes, etc) which can not
+// implement `Transport` themselves, connection has no addresses
+pub struct Plain<S> {
+  io: S,
+}
+
+impl<S> Plain<S> {
+  pub fn new(io: S) -> Plain<S> {
+    Plain { io }
+  }
+
+  pub fn get_ref(&self) -> &S {
+    &self.io
+  }
+
+  pub fn into_inner(self) -> S {
+    self.io
+  }
+}
+
+impl<S: io::Read> io::Read for Plain<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.io.read(buf)
+  }
+}
+
+impl<S: io::Write> io::Write for Plain<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.io.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.io.flush()
+  }
+}
+
+impl<S: AsyncRead> AsyncRead for Plain<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for Plain<S> {
+  // default impl would write only the first slice of vectored response
+  fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+    self.io.write_buf(buf)
+  }
+
+  fn poll_flush(&mut self) -> Poll<(), io::Error> {
+    self.io.poll_flush()
+  }
+
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    self.io.shutdown()
+  }
+}
+
+impl<S> Transport for Plain<S> where S: AsyncRead + AsyncWrite + Send + 'static {}
+
+// replays bytes read ahead of the stream consumer (protocol detection, PROXY header,
+// data received right after upgrade request) before reading from the stream itself
+pub struct Rewind<S> {
+  prefix: BytesMut,
+  io: S,
+}
+
+impl<S> Rewind<S> {
+  pub fn new(io: S, prefix: BytesMut) -> Rewind<S> {
+    Rewind { prefix, io }
+  }
+
+  pub fn get_ref(&self) -> &S {
+    &self.io
+  }
+
+  // bytes not read yet, for consumers parsing straight from their own buffer
+  pub fn take_prefix(&mut self) -> BytesMut {
+    self.prefix.take()
+  }
+}
+
+impl<S: io::Read> io::Read for Rewind<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.prefix.is_empty() {
+      return self.io.read(buf);
+    }
+
+    let amt = std::cmp::min(self.prefix.len(), buf.len());
+    buf[..amt].copy_from_slice(&self.prefix[..amt]);
+    self.prefix.advance(amt);
+    Ok(amt)
+  }
+}
+
+impl<S: io::Write> io::Write for Rewind<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.io.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.io.flush()
+  }
+}
+
+impl<S: AsyncRead> AsyncRead for Rewind<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for Rewind<S> {
+  fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+    self.io.write_buf(buf)
+  }
+
+  fn poll_flush(&mut self) -> Poll<(), io::Error> {
+    self.io.poll_flush()
+  }
+
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    self.io.shutdown()
+  }
+}
+
+impl<S: Transport> Transport for Rewind<S> {
+  fn peer_addr(&self) -> Option<SocketAddr> {
+    self.io.peer_addr()
+  }
+
+  fn local_addr(&self) -> Option<SocketAddr> {
+    self.io.local_addr()
+  }
+
+  fn peer_cred(&self) -> Option<PeerCred> {
+    self.io.peer_cred()
+  }
+
+  fn tls_info(&self) -> Option<TlsInfo> {
+    self.io.tls_info()
+  }
+}
+
+#[cfg(unix)]
+impl Transport for tokio::net::UnixStream {
+  fn peer_cred(&self) -> Option<PeerCred> {
+    unix_peer_cred(self)
+  }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn unix_peer_cred(socket: &tokio::net::UnixStream) -> Option<PeerCred> {
+  use std::os::unix::io::AsRawFd;
+
+  // SO_PEERCRED is read directly as tokio-uds does not expose pid
+  let mut cred = libc::ucred {
+    pid: 0,
+    uid: 0,
+    gid: 0,
+  };
+  let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+  let ret = unsafe {
+    libc::getsockopt(
+      socket.as_raw_fd(),
+      libc::SOL_SOCKET,
+      libc::SO_PEERCRED,
+      &mut cred as *mut libc::ucred as *mut libc::c_void,
+      &mut len,
+    )
+  };
+
+  if ret != 0 {
+    return None;
+  }
+
+  Some(PeerCred {
+    uid: cred.uid,
+    gid: cred.gid,
+    pid: Some(cred.pid),
+  })
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn unix_peer_cred(socket: &tokio::net::UnixStream) -> Option<PeerCred> {
+  socket.peer_cred().ok().map(|cred| PeerCred {
+    uid: cred.uid,
+    gid: cred.gid,
+    pid: None,
+  })
+}
+
+// object safe write side of transport, keeps vectored writes of the underlying stream
+pub(crate) trait WriteTransport: Send + Sync {
+  fn write_slice(&mut self, buf: &[u8]) -> Poll<usize, io::Error>;
+  fn write_vectored(&mut self, buf: &mut dyn Buf) -> Poll<usize, io::Error>;
+  // push out data buffered by the transport (tls records, etc)
+  fn flush(&mut self) -> Poll<(), io::Error>;
+  fn close(&mut self) -> Poll<(), io::Error>;
+}
+
+impl<W> WriteTransport for W
+where
+  W: AsyncWrite + Send + Sync,
+{
+  fn write_slice(&mut self, buf: &[u8]) -> Poll<usize, io::Error> {
+    self.poll_write(buf)
+  }
+
+  fn write_vectored(&mut self, mut buf: &mut dyn Buf) -> Poll<usize, io::Error> {
+    self.write_buf(&mut buf)
+  }
+
+  fn flush(&mut self) -> Poll<(), io::Error> {
+    self.poll_flush()
+  }
+
+  fn close(&mut self) -> Poll<(), io::Error> {
+    self.shutdown()
+  }
+}
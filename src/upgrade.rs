@@ -0,0 +1,111 @@
+use super::*;
+
+use futures::sync::oneshot;
+use std::io::{self, ErrorKind, Read, Write};
+
+// connection taken over from reader once 101 response is sent
+pub struct Upgraded {
+  // data received after request head (first bytes of the new protocol) is read first
+  pub read: transport::Rewind<Box<dyn AsyncRead + Send>>,
+  pub write: UpgradedWrite,
+}
+
+// write half of upgraded connection
+pub struct UpgradedWrite {
+  socket: WriteHalf,
+}
+
+impl UpgradedWrite {
+  pub(crate) fn new(socket: WriteHalf) -> UpgradedWrite {
+    UpgradedWrite { socket }
+  }
+}
+
+impl Write for UpgradedWrite {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self.socket.write_slice(buf)? {
+      Async::Ready(amt) => Ok(amt),
+      Async::NotReady => Err(ErrorKind::WouldBlock.into()),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self.socket.flush()? {
+      Async::Ready(()) => Ok(()),
+      Async::NotReady => Err(ErrorKind::WouldBlock.into()),
+    }
+  }
+}
+
+impl AsyncWrite for UpgradedWrite {
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    self.socket.close()
+  }
+}
+
+// both halves in one stream
+impl Read for Upgraded {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.read.read(buf)
+  }
+}
+
+impl AsyncRead for Upgraded {}
+
+impl Write for Upgraded {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.write.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.write.flush()
+  }
+}
+
+impl AsyncWrite for Upgraded {
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    self.write.shutdown()
+  }
+}
+
+// resolves once reader has handed over the connection, fails if response was not 101
+pub struct OnUpgrade {
+  receiver: oneshot::Receiver<Upgraded>,
+}
+
+impl OnUpgrade {
+  pub(crate) fn new(receiver: oneshot::Receiver<Upgraded>) -> OnUpgrade {
+    OnUpgrade { receiver }
+  }
+}
+
+impl Future for OnUpgrade {
+  type Item = Upgraded;
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    self
+      .receiver
+      .poll()
+      .map_err(|_| io::Error::other("Connection was not upgraded"))
+  }
+}
+
+// placeholder left in response once its socket is handed over
+pub(crate) struct Detached;
+
+impl Write for Detached {
+  fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+    Err(ErrorKind::NotConnected.into())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl AsyncWrite for Detached {
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    Ok(Async::Ready(()))
+  }
+}
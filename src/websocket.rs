@@ -0,0 +1,726 @@
+use super::*;
+
+use std::io::{self, ErrorKind};
+
+// appended to client key before hashing (RFC 6455 section 1.3)
+const GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// frame opcodes
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// close status codes
+const NORMAL: u16 = 1000;
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_DATA: u16 = 1007;
+const TOO_BIG: u16 = 1009;
+
+type Failure = (u16, &'static str);
+
+#[derive(Clone, Copy)]
+pub struct Config {
+  // limit for complete (reassembled) message, larger ones close connection with 1009
+  pub max_message_size: usize,
+  // outgoing data buffered before sink stops accepting messages
+  pub write_buffer_size: usize,
+}
+
+impl Config {
+  pub fn new() -> Config {
+    Config {
+      max_message_size: 16 * 1024 * 1024,
+      write_buffer_size: 64 * 1024,
+    }
+  }
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config::new()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+  Text(String),
+  Binary(Bytes),
+  Ping(Bytes),
+  Pong(Bytes),
+  Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+  pub code: u16,
+  pub reason: String,
+}
+
+// answer handshake and pass the connection to `func` once 101 response is sent, requests
+// which are not websocket handshakes get 400 (426 for unsupported protocol version)
+pub fn accept<F, R>(
+  mut req: request::Request,
+  mut res: response::Response,
+  config: Config,
+  func: F,
+) -> ReturnFuture
+where
+  F: FnOnce(WebSocket) -> R + Send + 'static,
+  R: Future<Item = (), Error = ()> + Send + 'static,
+{
+  let key = match handshake(&req) {
+    Ok(key) => key,
+    Err(status) => {
+      res.status(status);
+      if status == StatusCode::UPGRADE_REQUIRED {
+        res.headers_mut().insert(
+          "sec-websocket-version",
+          http::header::HeaderValue::from_static("13"),
+        );
+      }
+
+      return Box::new(res.write("").map(|res| (req, res)));
+    }
+  };
+
+  tokio::spawn(
+    req
+      .on_upgrade()
+      .map_err(|_| ())
+      .and_then(move |socket| func(WebSocket::new(socket, config))),
+  );
+
+  match res
+    .header("sec-websocket-accept", &key)
+    .and_then(|_| res.upgrade("websocket"))
+  {
+    Ok(write) => Box::new(write.map(|res| (req, res))),
+    Err(e) => Box::new(future::err(e)),
+  }
+}
+
+// validate handshake request and compute Sec-WebSocket-Accept value for it
+fn handshake(req: &request::Request) -> Result<String, StatusCode> {
+  let upgrade = req.upgrade().ok_or(StatusCode::BAD_REQUEST)?;
+  if req.method != "GET" || !response::has_token(upgrade.as_bytes(), b"websocket") {
+    return Err(StatusCode::BAD_REQUEST);
+  }
+
+  match req.headers.get("sec-websocket-version") {
+    Some(version) if response::trim(version) == b"13" => {}
+    _ => return Err(StatusCode::UPGRADE_REQUIRED),
+  }
+
+  // key is base64 encoded 16 byte nonce
+  let key = req
+    .headers
+    .get("sec-websocket-key")
+    .map(|key| response::trim(key))
+    .ok_or(StatusCode::BAD_REQUEST)?;
+  match base64::decode(key) {
+    Ok(ref nonce) if nonce.len() == 16 => {}
+    _ => return Err(StatusCode::BAD_REQUEST),
+  }
+
+  let mut sha = sha1::Sha1::new();
+  sha.update(key);
+  sha.update(GUID);
+  Ok(base64::encode(&sha.digest().bytes()))
+}
+
+// message stream and sink over upgraded connection, pings are answered and close
+// frames echoed automatically while reading
+pub struct WebSocket {
+  socket: upgrade::Upgraded,
+  config: Config,
+  read_buf: BytesMut,
+  write_buf: BytesMut,
+  // opcode and data of fragmented message received so far
+  fragments: Option<(u8, BytesMut)>,
+  close_sent: bool,
+  close_received: bool,
+}
+
+impl WebSocket {
+  pub fn new(mut socket: upgrade::Upgraded, config: Config) -> WebSocket {
+    let read_buf = socket.read.take_prefix();
+
+    WebSocket {
+      socket,
+      config,
+      read_buf,
+      write_buf: BytesMut::new(),
+      fragments: None,
+      close_sent: false,
+      close_received: false,
+    }
+  }
+
+  // parse single frame from read buffer and unmask its payload
+  fn decode(&mut self) -> Result<Option<(bool, u8, BytesMut)>, Failure> {
+    let buf = &self.read_buf;
+    if buf.len() < 2 {
+      return Ok(None);
+    }
+
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0f;
+    if buf[0] & 0x70 != 0 {
+      return Err((PROTOCOL_ERROR, "Reserved bits are set"));
+    }
+
+    if buf[1] & 0x80 == 0 {
+      return Err((PROTOCOL_ERROR, "Client frame is not masked"));
+    }
+
+    let (len, offset) = match buf[1] & 0x7f {
+      126 if buf.len() < 4 => return Ok(None),
+      126 => (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+      127 if buf.len() < 10 => return Ok(None),
+      127 => {
+        let mut len = [0; 8];
+        len.copy_from_slice(&buf[2..10]);
+        (u64::from_be_bytes(len), 10)
+      }
+      len => (u64::from(len), 2),
+    };
+
+    if opcode >= CLOSE && (!fin || len > 125) {
+      return Err((PROTOCOL_ERROR, "Invalid control frame"));
+    }
+
+    if len >> 63 != 0 {
+      return Err((PROTOCOL_ERROR, "Invalid frame length"));
+    }
+
+    // size is checked before payload is buffered
+    let received = self
+      .fragments
+      .as_ref()
+      .map_or(0, |(_, data)| data.len() as u64);
+    if received + len > self.config.max_message_size as u64 {
+      return Err((TOO_BIG, "Message is too big"));
+    }
+
+    let total = offset + 4 + len as usize;
+    if buf.len() < total {
+      let missing = total - buf.len();
+      self.read_buf.reserve(missing);
+      return Ok(None);
+    }
+
+    let mut mask = [0; 4];
+    mask.copy_from_slice(&buf[offset..offset + 4]);
+
+    let mut payload = self.read_buf.split_to(total);
+    payload.advance(offset + 4);
+    for (i, byte) in payload.iter_mut().enumerate() {
+      *byte ^= mask[i % 4];
+    }
+
+    Ok(Some((fin, opcode, payload)))
+  }
+
+  // handle received frame, returns message once it is complete
+  fn message(
+    &mut self,
+    (fin, opcode, data): (bool, u8, BytesMut),
+  ) -> Result<Option<Message>, Failure> {
+    match opcode {
+      TEXT | BINARY if self.fragments.is_some() => {
+        Err((PROTOCOL_ERROR, "Expected continuation frame"))
+      }
+      TEXT | BINARY if fin => complete(opcode, data).map(Some),
+      TEXT | BINARY => {
+        self.fragments = Some((opcode, data));
+        Ok(None)
+      }
+      CONTINUATION => {
+        let (opcode, mut message) = self
+          .fragments
+          .take()
+          .ok_or((PROTOCOL_ERROR, "Unexpected continuation frame"))?;
+        message.extend_from_slice(&data);
+
+        if fin {
+          return complete(opcode, message).map(Some);
+        }
+
+        self.fragments = Some((opcode, message));
+        Ok(None)
+      }
+      PING => {
+        if !self.close_sent {
+          self.encode(PONG, &data);
+        }
+
+        Ok(Some(Message::Ping(data.freeze())))
+      }
+      PONG => Ok(Some(Message::Pong(data.freeze()))),
+      CLOSE => {
+        let frame = close_frame(&data)?;
+        self.close_received = true;
+
+        // reply with the same status code
+        if !self.close_sent {
+          self.close_sent = true;
+          self.encode(CLOSE, &data[..std::cmp::min(data.len(), 2)]);
+        }
+
+        Ok(Some(Message::Close(frame)))
+      }
+      _ => Err((PROTOCOL_ERROR, "Unknown opcode")),
+    }
+  }
+
+  // server frames are sent unmasked and unfragmented
+  fn encode(&mut self, opcode: u8, payload: &[u8]) {
+    let buf = &mut self.write_buf;
+    buf.reserve(payload.len() + 10);
+    buf.put_u8(0x80 | opcode);
+
+    match payload.len() {
+      len if len < 126 => buf.put_u8(len as u8),
+      len if len <= 0xffff => {
+        buf.put_u8(126);
+        buf.put_u16_be(len as u16);
+      }
+      len => {
+        buf.put_u8(127);
+        buf.put_u64_be(len as u64);
+      }
+    }
+
+    buf.extend_from_slice(payload);
+  }
+
+  fn write(&mut self) -> Poll<(), io::Error> {
+    while !self.write_buf.is_empty() {
+      let amt = futures::try_ready!(self.socket.poll_write(&self.write_buf));
+      if amt == 0 {
+        return Err(ErrorKind::WriteZero.into());
+      }
+
+      self.write_buf.advance(amt);
+    }
+
+    Ok(Async::Ready(()))
+  }
+
+  // protocol violation closes connection with given status code
+  fn fail(&mut self, (code, reason): Failure) -> io::Error {
+    if !self.close_sent {
+      self.close_sent = true;
+      self.encode(CLOSE, &code.to_be_bytes());
+      let _ = self.write();
+    }
+
+    self.close_received = true;
+    io::Error::new(ErrorKind::InvalidData, reason)
+  }
+}
+
+// text messages have to be valid utf-8
+fn complete(opcode: u8, data: BytesMut) -> Result<Message, Failure> {
+  if opcode == BINARY {
+    return Ok(Message::Binary(data.freeze()));
+  }
+
+  String::from_utf8(data.to_vec())
+    .map(Message::Text)
+    .map_err(|_| (INVALID_DATA, "Invalid UTF-8 in text message"))
+}
+
+fn close_frame(data: &[u8]) -> Result<Option<CloseFrame>, Failure> {
+  match data.len() {
+    0 => return Ok(None),
+    1 => return Err((PROTOCOL_ERROR, "Invalid close frame")),
+    _ => {}
+  }
+
+  // codes allowed on the wire (RFC 6455 section 7.4)
+  let code = u16::from_be_bytes([data[0], data[1]]);
+  match code {
+    1000..=1003 | 1007..=1011 | 3000..=4999 => {}
+    _ => return Err((PROTOCOL_ERROR, "Invalid close code")),
+  }
+
+  let reason =
+    std::str::from_utf8(&data[2..]).map_err(|_| (INVALID_DATA, "Invalid UTF-8 in close reason"))?;
+  Ok(Some(CloseFrame {
+    code,
+    reason: reason.to_string(),
+  }))
+}
+
+impl Stream for WebSocket {
+  type Item = Message;
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+    loop {
+      // pong and close replies are flushed along with reading
+      if !self.write_buf.is_empty() {
+        self.write()?;
+      }
+
+      // connection is dropped once close frames were exchanged
+      if self.close_received {
+        futures::try_ready!(self.poll_complete());
+        return Ok(Async::Ready(None));
+      }
+
+      let frame = match self.decode() {
+        Ok(Some(frame)) => frame,
+        Ok(None) => {
+          self.read_buf.reserve(4096);
+          if futures::try_ready!(AsyncRead::read_buf(&mut self.socket, &mut self.read_buf)) == 0 {
+            // client went away without close handshake
+            self.close_received = true;
+            return Ok(Async::Ready(None));
+          }
+
+          continue;
+        }
+        Err(failure) => return Err(self.fail(failure)),
+      };
+
+      match self.message(frame) {
+        Ok(Some(message)) => return Ok(Async::Ready(Some(message))),
+        Ok(None) => {}
+        Err(failure) => return Err(self.fail(failure)),
+      }
+    }
+  }
+}
+
+impl Sink for WebSocket {
+  type SinkItem = Message;
+  type SinkError = io::Error;
+
+  fn start_send(
+    &mut self,
+    message: Self::SinkItem,
+  ) -> futures::StartSend<Self::SinkItem, Self::SinkError> {
+    if self.close_sent {
+      return Err(io::Error::new(
+        ErrorKind::NotConnected,
+        "WebSocket is closed",
+      ));
+    }
+
+    if self.write_buf.len() >= self.config.write_buffer_size {
+      self.write()?;
+      if self.write_buf.len() >= self.config.write_buffer_size {
+        return Ok(AsyncSink::NotReady(message));
+      }
+    }
+
+    match message {
+      Message::Text(text) => self.encode(TEXT, text.as_bytes()),
+      Message::Binary(data) => self.encode(BINARY, &data),
+      Message::Ping(ref data) | Message::Pong(ref data) if data.len() > 125 => {
+        return Err(io::Error::new(
+          ErrorKind::InvalidInput,
+          "Control frame is too long",
+        ));
+      }
+      Message::Ping(data) => self.encode(PING, &data),
+      Message::Pong(data) => self.encode(PONG, &data),
+      Message::Close(frame) => {
+        let mut payload = Vec::new();
+        if let Some(frame) = frame {
+          payload.extend_from_slice(&frame.code.to_be_bytes());
+          payload.extend_from_slice(frame.reason.as_bytes());
+        }
+
+        if payload.len() > 125 {
+          return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Close reason is too long",
+          ));
+        }
+
+        self.close_sent = true;
+        self.encode(CLOSE, &payload);
+      }
+    }
+
+    Ok(AsyncSink::Ready)
+  }
+
+  fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+    futures::try_ready!(self.write());
+    self.socket.poll_flush()
+  }
+
+  fn close(&mut self) -> Poll<(), Self::SinkError> {
+    if !self.close_sent {
+      self.close_sent = true;
+      self.encode(CLOSE, &NORMAL.to_be_bytes());
+    }
+
+    self.poll_complete()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+  // masked client frame
+  fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = vec![if fin { 0x80 } else { 0 } | opcode];
+    match payload.len() {
+      len if len < 126 => buf.push(0x80 | len as u8),
+      len if len <= 0xffff => {
+        buf.push(0x80 | 126);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+      }
+      len => {
+        buf.push(0x80 | 127);
+        buf.extend_from_slice(&(len as u64).to_be_bytes());
+      }
+    }
+
+    buf.extend_from_slice(&MASK);
+    buf.extend(
+      payload
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ MASK[i % 4]),
+    );
+    buf
+  }
+
+  // connection with `data` already received, nothing more is read from the socket
+  fn socket(data: &[u8], config: Config) -> WebSocket {
+    let upgraded = upgrade::Upgraded {
+      read: transport::Rewind::new(Box::new(&b""[..]), BytesMut::from(data)),
+      write: upgrade::UpgradedWrite::new(Box::new(io::sink())),
+    };
+    WebSocket::new(upgraded, config)
+  }
+
+  // decode and handle frames until buffer runs out or protocol fails
+  fn receive(ws: &mut WebSocket) -> Result<Vec<Message>, Failure> {
+    let mut messages = Vec::new();
+    while let Some(frame) = ws.decode()? {
+      messages.extend(ws.message(frame)?);
+    }
+    Ok(messages)
+  }
+
+  fn receive_frames(frames: &[Vec<u8>]) -> Result<Vec<Message>, Failure> {
+    receive(&mut socket(&frames.concat(), Config::new()))
+  }
+
+  #[test]
+  fn payload_is_unmasked() {
+    let messages = receive_frames(&[frame(true, TEXT, b"hello")]).unwrap();
+    assert_eq!(messages, [Message::Text("hello".to_string())]);
+  }
+
+  #[test]
+  fn extended_lengths() {
+    for len in &[125, 126, 0xffff, 0x10000] {
+      let payload = vec![7; *len];
+      let messages = receive_frames(&[frame(true, BINARY, &payload)]).unwrap();
+      assert_eq!(messages, [Message::Binary(Bytes::from(payload))]);
+    }
+  }
+
+  #[test]
+  fn partial_frame_waits_for_more_data() {
+    let data = frame(true, TEXT, b"hello");
+
+    for at in 0..data.len() {
+      let mut ws = socket(&data[..at], Config::new());
+      assert_eq!(ws.decode(), Ok(None));
+
+      ws.read_buf.extend_from_slice(&data[at..]);
+      let messages = receive(&mut ws).unwrap();
+      assert_eq!(messages, [Message::Text("hello".to_string())]);
+    }
+  }
+
+  #[test]
+  fn unmasked_frame_is_rejected() {
+    let mut ws = socket(b"\x81\x05hello", Config::new());
+    assert_eq!(
+      ws.decode(),
+      Err((PROTOCOL_ERROR, "Client frame is not masked"))
+    );
+  }
+
+  #[test]
+  fn reserved_bits_are_rejected() {
+    let mut data = frame(true, TEXT, b"a");
+    data[0] |= 0x40;
+    assert_eq!(
+      receive_frames(&[data.clone()]),
+      Err((PROTOCOL_ERROR, "Reserved bits are set"))
+    );
+  }
+
+  #[test]
+  fn invalid_control_frames_are_rejected() {
+    let fragmented = frame(false, PING, b"a");
+    let too_long = frame(true, PING, &[0; 126]);
+
+    for data in &[fragmented, too_long] {
+      assert_eq!(
+        receive(&mut socket(data, Config::new())),
+        Err((PROTOCOL_ERROR, "Invalid control frame"))
+      );
+    }
+  }
+
+  #[test]
+  fn unknown_opcode_is_rejected() {
+    assert_eq!(
+      receive_frames(&[frame(true, 0x3, b"")]),
+      Err((PROTOCOL_ERROR, "Unknown opcode"))
+    );
+  }
+
+  #[test]
+  fn fragments_are_joined_around_control_frames() {
+    let mut ws = socket(
+      &[
+        frame(false, TEXT, b"hel"),
+        frame(true, PING, b"p"),
+        frame(false, CONTINUATION, b"lo "),
+        frame(true, PONG, b""),
+        frame(true, CONTINUATION, "wörld".as_bytes()),
+      ]
+      .concat(),
+      Config::new(),
+    );
+
+    assert_eq!(
+      receive(&mut ws).unwrap(),
+      [
+        Message::Ping(Bytes::from("p")),
+        Message::Pong(Bytes::new()),
+        Message::Text("hello wörld".to_string()),
+      ]
+    );
+    // ping is answered right away
+    assert_eq!(ws.write_buf, &b"\x8a\x01p"[..]);
+  }
+
+  #[test]
+  fn fragment_order_is_checked() {
+    assert_eq!(
+      receive_frames(&[frame(true, CONTINUATION, b"a")]),
+      Err((PROTOCOL_ERROR, "Unexpected continuation frame"))
+    );
+    assert_eq!(
+      receive_frames(&[frame(false, TEXT, b"a"), frame(true, BINARY, b"b")]),
+      Err((PROTOCOL_ERROR, "Expected continuation frame"))
+    );
+  }
+
+  #[test]
+  fn text_has_to_be_utf8() {
+    assert_eq!(
+      receive_frames(&[frame(true, TEXT, b"\xff")]),
+      Err((INVALID_DATA, "Invalid UTF-8 in text message"))
+    );
+
+    // code point may be split between fragments
+    let text = "ö".as_bytes();
+    let messages = receive_frames(&[
+      frame(false, TEXT, &text[..1]),
+      frame(true, CONTINUATION, &text[1..]),
+    ]);
+    assert_eq!(messages, Ok(vec![Message::Text("ö".to_string())]));
+  }
+
+  #[test]
+  fn message_size_is_limited() {
+    let mut config = Config::new();
+    config.max_message_size = 4;
+
+    let mut ws = socket(&frame(true, BINARY, b"abcd"), config);
+    assert_eq!(receive(&mut ws).unwrap().len(), 1);
+
+    // limit applies to the whole message, before its payload is buffered
+    let data = [frame(false, TEXT, b"abc"), frame(true, CONTINUATION, b"de")].concat();
+    let mut ws = socket(&data, config);
+    assert_eq!(receive(&mut ws), Err((TOO_BIG, "Message is too big")));
+
+    let mut ws = socket(&frame(true, BINARY, &[0; 1000])[..8], config);
+    assert_eq!(ws.decode(), Err((TOO_BIG, "Message is too big")));
+  }
+
+  #[test]
+  fn close_is_echoed_with_its_code() {
+    let mut payload = 1001u16.to_be_bytes().to_vec();
+    payload.extend_from_slice(b"bye");
+
+    let mut ws = socket(&frame(true, CLOSE, &payload), Config::new());
+    let messages = receive(&mut ws).unwrap();
+
+    assert_eq!(
+      messages,
+      [Message::Close(Some(CloseFrame {
+        code: 1001,
+        reason: "bye".to_string(),
+      }))]
+    );
+    assert!(ws.close_received && ws.close_sent);
+    assert_eq!(ws.write_buf, &b"\x88\x02\x03\xe9"[..]);
+  }
+
+  #[test]
+  fn close_frame_payload() {
+    assert_eq!(close_frame(b""), Ok(None));
+    assert_eq!(
+      close_frame(b"\x03"),
+      Err((PROTOCOL_ERROR, "Invalid close frame"))
+    );
+
+    for code in &[1000u16, 1003, 1007, 1011, 3000, 4999] {
+      let frame = close_frame(&code.to_be_bytes()).unwrap().unwrap();
+      assert_eq!(frame.code, *code);
+      assert_eq!(frame.reason, "");
+    }
+
+    // reserved and never sent codes
+    for code in &[0u16, 999, 1004, 1005, 1006, 1012, 1015, 2999, 5000] {
+      assert_eq!(
+        close_frame(&code.to_be_bytes()),
+        Err((PROTOCOL_ERROR, "Invalid close code")),
+        "{}",
+        code
+      );
+    }
+
+    assert_eq!(
+      close_frame(b"\x03\xe8\xff"),
+      Err((INVALID_DATA, "Invalid UTF-8 in close reason"))
+    );
+  }
+
+  #[test]
+  fn server_frames_are_not_masked() {
+    let mut ws = socket(b"", Config::new());
+    ws.encode(TEXT, b"hi");
+    ws.encode(BINARY, &[0; 126]);
+    ws.encode(BINARY, &[0; 0x10000]);
+
+    assert_eq!(&ws.write_buf[..4], b"\x81\x02hi");
+    assert_eq!(&ws.write_buf[4..8], b"\x82\x7e\x00\x7e");
+    assert_eq!(
+      &ws.write_buf[134..144],
+      b"\x82\x7f\x00\x00\x00\x00\x00\x01\x00\x00"
+    );
+  }
+}
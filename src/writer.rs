@@ -1,30 +1,203 @@
 use super::*;
 
-use std::io;
+use std::io::{self, Cursor};
 use std::mem;
+use std::time::{Duration, Instant};
 
+use bytes::buf::Chain;
+use bytes::Buf;
 use futures::try_ready;
+use http::header::{CONNECTION, CONTENT_LENGTH, DATE, TRANSFER_ENCODING};
+use tokio::timer::Delay;
 
-pub struct WriteAll {
-  state: State,
+// chunk terminator of the last data followed by the last chunk
+const LAST_DATA_END: &[u8] = b"\r\n0\r\n\r\n";
+
+// response head independent of the protocol, framing and connection headers are
+// added by the writer of the connection
+pub(crate) struct Head {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+  // None means body is streamed
+  pub content_length: Option<usize>,
+  // http/1 connection state of the request
+  pub version: u8,
+  pub keep_alive: bool,
+}
+
+// protocol side of the response, parts are queued one at a time (head and/or body data)
+// and sent by `poll_flush` before the next one is queued
+pub(crate) trait ResponseWriter: Send + Sync {
+  fn head(&mut self, head: Head);
+  // `end` marks the last data of the body
+  fn data(&mut self, data: Bytes, end: bool);
+  // bytes of queued parts which were not sent yet
+  fn remaining(&self) -> usize;
+  fn poll_flush(&mut self) -> Poll<(), io::Error>;
+  fn poll_close(&mut self) -> Poll<(), io::Error>;
+
+  // raw socket for upgraded connection
+  fn detach(&mut self) -> Option<WriteHalf> {
+    None
+  }
+}
+
+// head or chunk size line, body data and chunk terminator
+type Parts = Chain<Chain<Cursor<BytesMut>, Cursor<Bytes>>, Cursor<&'static [u8]>>;
+
+// head and chunk framing are sent together with body data in one vectored write,
+// body is never copied
+pub(crate) struct Http1Writer {
+  socket: WriteHalf,
+  prefix: BytesMut,
+  body: Bytes,
+  suffix: &'static [u8],
+  writing: Option<Parts>,
+  chunked: bool,
+}
+
+impl Http1Writer {
+  pub(crate) fn new(socket: WriteHalf) -> Http1Writer {
+    Http1Writer {
+      socket,
+      prefix: BytesMut::new(),
+      body: Bytes::new(),
+      suffix: b"",
+      writing: None,
+      chunked: false,
+    }
+  }
+
+  fn prefix(&mut self) -> &mut BytesMut {
+    if self.prefix.capacity() == 0 {
+      self.prefix = pool::get(1024);
+    }
+
+    &mut self.prefix
+  }
+}
+
+impl ResponseWriter for Http1Writer {
+  fn head(&mut self, head: Head) {
+    let buf = self.prefix();
+    response::set_status_line(buf, head.status);
+
+    if !head.headers.contains_key(DATE) {
+      date::set_date_header(buf);
+    }
+
+    for (name, value) in head.headers.iter() {
+      // framing and connection headers are always computed from the body and request
+      if name != CONTENT_LENGTH && name != TRANSFER_ENCODING && name != CONNECTION {
+        response::push(buf, name.as_str().as_bytes());
+        response::push(buf, b": ");
+        response::push(buf, value.as_bytes());
+        response::push(buf, b"\r\n");
+      }
+    }
+
+    // connection belongs to the new protocol, there is no body
+    if head.status == StatusCode::SWITCHING_PROTOCOLS {
+      response::push(buf, b"connection: upgrade\r\n\r\n");
+      self.chunked = false;
+      return;
+    }
+
+    if !head.keep_alive {
+      response::push(buf, b"connection: close\r\n");
+    } else if head.version == 0 {
+      response::push(buf, b"connection: keep-alive\r\n");
+    }
+
+    // HTTP/1.0 clients get streamed body delimited by closing the connection
+    match head.content_length {
+      Some(0) => response::push(buf, b"content-length: 0\r\n\r\n"),
+      Some(len) => {
+        response::push(buf, b"content-length: ");
+        response::push(buf, len.to_string().as_bytes());
+        response::push(buf, b"\r\n\r\n");
+      }
+      None if head.version > 0 => response::push(buf, b"transfer-encoding: chunked\r\n\r\n"),
+      None => response::push(buf, b"\r\n"),
+    }
+
+    self.chunked = head.content_length.is_none() && head.version > 0;
+  }
+
+  fn data(&mut self, data: Bytes, end: bool) {
+    debug_assert!(self.writing.is_none() && self.body.is_empty());
+
+    if self.chunked {
+      if !data.is_empty() {
+        let size = format!("{:x}\r\n", data.len());
+        response::push(self.prefix(), size.as_bytes());
+        self.suffix = if end { LAST_DATA_END } else { b"\r\n" };
+      } else if end {
+        response::push(self.prefix(), chunk::LAST_CHUNK);
+      }
+    }
+
+    self.body = data;
+  }
+
+  fn remaining(&self) -> usize {
+    match &self.writing {
+      Some(buf) => buf.remaining(),
+      None => self.prefix.len() + self.body.len() + self.suffix.len(),
+    }
+  }
+
+  fn poll_flush(&mut self) -> Poll<(), io::Error> {
+    if self.writing.is_none() && self.remaining() > 0 {
+      let prefix = mem::replace(&mut self.prefix, BytesMut::new());
+      let body = mem::replace(&mut self.body, Bytes::new());
+      let suffix = mem::replace(&mut self.suffix, b"");
+      self.writing = Some(Chain::new(
+        Chain::new(Cursor::new(prefix), Cursor::new(body)),
+        Cursor::new(suffix),
+      ));
+    }
+
+    if let Some(buf) = self.writing.as_mut() {
+      while buf.has_remaining() {
+        if try_ready!(self.socket.write_vectored(buf)) == 0 {
+          return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "zero-length write",
+          ));
+        }
+      }
+
+      if let Some(buf) = self.writing.take() {
+        let (parts, _) = buf.into_inner();
+        let (prefix, _) = parts.into_inner();
+        pool::put(prefix.into_inner());
+      }
+    }
+
+    // response is written only once transport has nothing buffered
+    self.socket.flush()
+  }
+
+  fn poll_close(&mut self) -> Poll<(), io::Error> {
+    self.socket.close()
+  }
+
+  fn detach(&mut self) -> Option<WriteHalf> {
+    Some(mem::replace(&mut self.socket, Box::new(upgrade::Detached)))
+  }
 }
 
-enum State {
-  Writing {
-    a: response::Response,
-    buf: BytesMut,
-    pos: usize,
-  },
-  Empty,
+// resolves once queued parts of the response are sent
+pub struct WriteAll {
+  res: Option<response::Response>,
+  timer: Option<Delay>,
 }
 
-pub fn write_all(a: response::Response, res: BytesMut) -> WriteAll {
+pub fn write_all(res: response::Response) -> WriteAll {
   WriteAll {
-    state: State::Writing {
-      a,
-      buf: res,
-      pos: 0,
-    },
+    res: Some(res),
+    timer: None,
   }
 }
 
@@ -33,29 +206,178 @@ impl Future for WriteAll {
   type Error = io::Error;
 
   fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-    match self.state {
-      State::Writing {
-        ref mut a,
-        ref buf,
-        ref mut pos,
-      } => {
-        while *pos < buf.len() {
-          let n = try_ready!(a.socket.poll_write(&buf[*pos..]));
-          *pos += n;
-          if n == 0 {
+    let res = self.res.as_mut().expect("poll a WriteAll after it's done");
+    try_ready!(poll_write(res, &mut self.timer));
+
+    Ok(Async::Ready(
+      self.res.take().expect("poll a WriteAll after it's done"),
+    ))
+  }
+}
+
+pub struct WriteStream<S> {
+  res: Option<response::Response>,
+  stream: S,
+  // bytes left to send when content-length is known
+  remaining: Option<usize>,
+  done: bool,
+  timer: Option<Delay>,
+}
+
+pub fn write_stream<S>(
+  res: response::Response,
+  stream: S,
+  content_length: Option<usize>,
+) -> WriteStream<S> {
+  WriteStream {
+    res: Some(res),
+    stream,
+    remaining: content_length,
+    done: false,
+    timer: None,
+  }
+}
+
+impl<S> Future for WriteStream<S>
+where
+  S: Stream<Item = Bytes, Error = io::Error>,
+{
+  type Item = response::Response;
+  type Error = io::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    loop {
+      // next item is requested from the stream only once previous one is sent, stream may
+      // not produce it for a while and data sent so far has to reach client
+      let res = self
+        .res
+        .as_mut()
+        .expect("poll a WriteStream after it's done");
+      try_ready!(poll_write(res, &mut self.timer));
+
+      if self.done {
+        return Ok(Async::Ready(
+          self.res.take().expect("poll a WriteStream after it's done"),
+        ));
+      }
+
+      match try_ready!(self.stream.poll()) {
+        Some(data) => {
+          if let Some(remaining) = self.remaining.as_mut() {
+            if data.len() > *remaining {
+              return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Stream is longer than content-length",
+              ));
+            }
+            *remaining -= data.len();
+          }
+
+          res.writer.data(data, false);
+        }
+        None => {
+          if matches!(self.remaining, Some(remaining) if remaining > 0) {
             return Err(io::Error::new(
-              io::ErrorKind::WriteZero,
-              "zero-length write",
+              io::ErrorKind::InvalidData,
+              "Stream is shorter than content-length",
             ));
           }
+
+          self.done = true;
+          res.writer.data(Bytes::new(), true);
         }
       }
-      State::Empty => panic!("poll a WriteAll after it's done"),
     }
+  }
+}
 
-    match mem::replace(&mut self.state, State::Empty) {
-      State::Writing { a, .. } => Ok(a.into()),
-      State::Empty => panic!(),
+// send queued parts, write which could not make any progress for `write_timeout` fails,
+// time spent waiting for the body stream itself is not counted
+fn poll_write(res: &mut response::Response, timer: &mut Option<Delay>) -> Poll<(), io::Error> {
+  let remaining = res.writer.remaining();
+
+  match res.writer.poll_flush()? {
+    Async::Ready(()) => {
+      *timer = None;
+      Ok(Async::Ready(()))
     }
+    Async::NotReady => {
+      if res.writer.remaining() < remaining {
+        *timer = None;
+      }
+
+      poll_stalled(timer, res.write_timeout)?;
+      Ok(Async::NotReady)
+    }
+  }
+}
+
+fn poll_stalled(timer: &mut Option<Delay>, timeout: Duration) -> Result<(), io::Error> {
+  let timer = timer.get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+
+  match timer.poll() {
+    Ok(Async::Ready(_)) => Err(io::Error::new(
+      io::ErrorKind::TimedOut,
+      "Response write timed out",
+    )),
+    Ok(Async::NotReady) => Ok(()),
+    Err(e) => Err(io::Error::other(e)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http::header::HeaderName;
+
+  fn head(headers: &[(&str, &str)], content_length: Option<usize>, keep_alive: bool) -> String {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+      map.append(
+        HeaderName::from_bytes(name.as_bytes()).unwrap(),
+        value.parse().unwrap(),
+      );
+    }
+
+    let mut writer = Http1Writer::new(Box::new(io::sink()));
+    writer.head(Head {
+      status: StatusCode::OK,
+      headers: map,
+      content_length,
+      version: 1,
+      keep_alive,
+    });
+    String::from_utf8(writer.prefix.to_vec()).unwrap()
+  }
+
+  #[test]
+  fn user_framing_headers_are_replaced() {
+    let framing = [
+      ("content-length", "100"),
+      ("transfer-encoding", "chunked"),
+      ("connection", "keep-alive"),
+      ("x-test", "1"),
+    ];
+
+    let head = head(&framing, Some(5), true);
+    assert!(head.contains("x-test: 1\r\n"));
+    assert!(head.ends_with("content-length: 5\r\n\r\n"));
+    assert!(!head.contains("content-length: 100"));
+    assert!(!head.contains("transfer-encoding"));
+    assert!(!head.contains("connection"));
+  }
+
+  #[test]
+  fn streamed_body_gets_chunked_framing_only() {
+    let head = head(&[("content-length", "100")], None, true);
+    assert!(head.ends_with("\r\ntransfer-encoding: chunked\r\n\r\n"));
+    assert!(!head.contains("content-length"));
+  }
+
+  #[test]
+  fn connection_header_follows_connection_state() {
+    let head = head(&[("connection", "upgrade, keep-alive")], Some(0), false);
+    assert_eq!(head.matches("connection").count(), 1);
+    assert!(head.ends_with("connection: close\r\ncontent-length: 0\r\n\r\n"));
   }
 }
@@ -0,0 +1,262 @@
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use bytes::Buf;
+
+use peta::config::Config;
+use peta::reader::Reader;
+use peta::router::Router;
+use peta::transport::Plain;
+use peta::*;
+
+#[derive(Default)]
+struct Output {
+  written: Vec<u8>,
+  closed: bool,
+  largest_read: usize,
+  reads: Vec<usize>,
+  write_bufs: usize,
+}
+
+// client side of in-memory connection, every element of `reads` is returned by a
+// separate read and the client hangs up once all of them are consumed
+pub struct Pipe {
+  reads: VecDeque<Vec<u8>>,
+  output: Arc<Mutex<Output>>,
+  // like tls, writes are kept until flush and flush or shutdown are not ready on first poll,
+  // anything left unflushed at shutdown is lost
+  buffered: Option<Vec<u8>>,
+  stalled: bool,
+  // client stays connected without sending anything once reads are consumed
+  keep_open: bool,
+}
+
+impl Pipe {
+  fn stall(&mut self) -> bool {
+    if self.buffered.is_none() {
+      return false;
+    }
+
+    self.stalled = !self.stalled;
+    if self.stalled {
+      futures::task::current().notify();
+    }
+    self.stalled
+  }
+}
+
+impl Read for Pipe {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let mut output = self.output.lock().unwrap();
+    output.largest_read = std::cmp::max(output.largest_read, buf.len());
+    output.reads.push(buf.len());
+
+    let mut data = match self.reads.pop_front() {
+      Some(data) => data,
+      // server is woken up by its own timers
+      None if self.keep_open => return Err(io::ErrorKind::WouldBlock.into()),
+      None => return Ok(0),
+    };
+
+    let amt = std::cmp::min(data.len(), buf.len());
+    if amt < data.len() {
+      self.reads.push_front(data.split_off(amt));
+    }
+
+    buf[..amt].copy_from_slice(&data);
+    Ok(amt)
+  }
+}
+
+impl Write for Pipe {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self.buffered.as_mut() {
+      Some(buffered) => buffered.extend_from_slice(buf),
+      None => self.output.lock().unwrap().written.extend_from_slice(buf),
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if self.stall() {
+      return Err(io::ErrorKind::WouldBlock.into());
+    }
+
+    if let Some(buffered) = self.buffered.as_mut() {
+      let mut output = self.output.lock().unwrap();
+      output.written.append(buffered);
+    }
+    Ok(())
+  }
+}
+
+impl AsyncRead for Pipe {}
+
+impl AsyncWrite for Pipe {
+  // takes all slices at once like a vectored socket write
+  fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+    self.output.lock().unwrap().write_bufs += 1;
+
+    let mut amt = 0;
+    while buf.has_remaining() {
+      let len = self.write(buf.bytes())?;
+      buf.advance(len);
+      amt += len;
+    }
+    Ok(Async::Ready(amt))
+  }
+
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    if self.stall() {
+      return Ok(Async::NotReady);
+    }
+
+    self.output.lock().unwrap().closed = true;
+    Ok(Async::Ready(()))
+  }
+}
+
+pub struct Served {
+  pub result: Result<(), io::Error>,
+  pub output: String,
+  // server closed the connection
+  pub closed: bool,
+  // biggest buffer server asked to fill
+  pub largest_read: usize,
+  // size of every buffer server asked to fill, in order
+  pub reads: Vec<usize>,
+  // calls of `write_buf` reaching the pipe
+  pub write_bufs: usize,
+}
+
+impl Served {
+  pub fn status_lines(&self) -> Vec<&str> {
+    // status line of pipelined response follows previous body directly
+    self
+      .output
+      .match_indices("HTTP/1.1 ")
+      .map(|(start, _)| {
+        let line = &self.output[start..];
+        &line[..line.find("\r\n").unwrap_or(line.len())]
+      })
+      .collect()
+  }
+}
+
+pub fn serve(router: &Router, config: &Config, reads: &[&[u8]]) -> Served {
+  run(router, config, reads, None, false, future::ok)
+}
+
+// transport buffering writes until flushed
+pub fn serve_buffered(router: &Router, config: &Config, reads: &[&[u8]]) -> Served {
+  run(router, config, reads, Some(Vec::new()), false, future::ok)
+}
+
+// client goes silent after the reads instead of hanging up, connection ends only when
+// server closes it
+pub fn serve_open(router: &Router, config: &Config, reads: &[&[u8]]) -> Served {
+  run(router, config, reads, None, true, future::ok)
+}
+
+// pipe wrapped in another transport (rewind, PROXY header, etc) before it is served
+pub fn serve_wrapped<F, W>(router: &Router, config: &Config, reads: &[&[u8]], wrap: F) -> Served
+where
+  F: FnOnce(Plain<Pipe>) -> W,
+  W: IntoFuture<Error = io::Error>,
+  W::Item: transport::Transport,
+{
+  run(router, config, reads, None, false, wrap)
+}
+
+fn run<F, W>(
+  router: &Router,
+  config: &Config,
+  reads: &[&[u8]],
+  buffered: Option<Vec<u8>>,
+  keep_open: bool,
+  wrap: F,
+) -> Served
+where
+  F: FnOnce(Plain<Pipe>) -> W,
+  W: IntoFuture<Error = io::Error>,
+  W::Item: transport::Transport,
+{
+  let output = Arc::new(Mutex::new(Output::default()));
+  let pipe = Pipe {
+    reads: reads.iter().map(|data| data.to_vec()).collect(),
+    output: output.clone(),
+    buffered,
+    stalled: false,
+    keep_open,
+  };
+
+  let reader = wrap(Plain::new(pipe))
+    .into_future()
+    .and_then(|socket| Reader::with_config(socket, router, config))
+    .map(|_| ());
+  let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+  let result = runtime.block_on(reader);
+  // upgraded connections are served by spawned tasks
+  runtime.run().unwrap();
+
+  let output = output.lock().unwrap();
+  Served {
+    result,
+    output: String::from_utf8_lossy(&output.written).into_owned(),
+    closed: output.closed,
+    largest_read: output.largest_read,
+    reads: output.reads.clone(),
+    write_bufs: output.write_bufs,
+  }
+}
+
+// /hello responds with fixed body, /chunks and /stream with streamed body, /echo with
+// request body and /silent never writes
+pub fn router() -> Router {
+  let mut router = Router::new();
+
+  router.add("GET", "/hello", |(req, res)| {
+    Box::new(res.write("hello").map(|res| (req, res)))
+  });
+
+  router.add("GET", "/addr", |(req, res)| {
+    let body = format!("{:?} {:?}", req.peer_addr(), req.local_addr());
+    Box::new(res.write(body).map(|res| (req, res)))
+  });
+
+  router.add("GET", "/chunks", |(req, res)| {
+    let fut = res
+      .write_head()
+      .and_then(|res| res.write_chunk("ab"))
+      .and_then(|res| res.write_chunk("cde"))
+      .and_then(|res| res.finish());
+    Box::new(fut.map(|res| (req, res)))
+  });
+
+  router.add("GET", "/stream", |(req, res)| {
+    let body = stream::iter_ok(vec![Bytes::from("ab"), Bytes::from("cde")]);
+    Box::new(res.write_stream(body, Some(5)).map(|res| (req, res)))
+  });
+
+  router.add("POST", "/echo", |(mut req, res)| {
+    req.on_data(|(mut req, res)| {
+      if req.is_last() {
+        let body = req.data().take();
+        return Box::new(res.write(body).map(|res| (req, res)));
+      }
+
+      Box::new(future::ok((req, res)))
+    });
+
+    Box::new(future::ok((req, res)))
+  });
+
+  router.add("GET", "/silent", |(req, res)| {
+    Box::new(future::ok((req, res)))
+  });
+
+  router
+}
@@ -0,0 +1,269 @@
+#![cfg(feature = "http2")]
+
+mod common;
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::task::{self, Task};
+use h2::client::{self, SendRequest};
+use h2::RecvStream;
+use http::{Request, Response};
+use tokio::runtime::current_thread::Runtime;
+
+use peta::config::Config;
+use peta::http2;
+use peta::router::Router;
+use peta::transport::Plain;
+use peta::*;
+
+#[derive(Default)]
+struct Half {
+  data: VecDeque<u8>,
+  closed: bool,
+  reader: Option<Task>,
+}
+
+// one direction of in-memory connection
+type Channel = Arc<Mutex<Half>>;
+
+// end of in-memory connection, both ends are driven by the same runtime
+struct Duplex {
+  read: Channel,
+  write: Channel,
+}
+
+fn duplex() -> (Duplex, Duplex) {
+  let (a, b) = (Channel::default(), Channel::default());
+  let client = Duplex {
+    read: a.clone(),
+    write: b.clone(),
+  };
+  (client, Duplex { read: b, write: a })
+}
+
+impl Read for Duplex {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let mut half = self.read.lock().unwrap();
+    if half.data.is_empty() {
+      if half.closed {
+        return Ok(0);
+      }
+
+      half.reader = Some(task::current());
+      return Err(io::ErrorKind::WouldBlock.into());
+    }
+
+    let amt = std::cmp::min(buf.len(), half.data.len());
+    for (dst, src) in buf.iter_mut().zip(half.data.drain(..amt)) {
+      *dst = src;
+    }
+    Ok(amt)
+  }
+}
+
+impl Write for Duplex {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut half = self.write.lock().unwrap();
+    half.data.extend(buf);
+    if let Some(reader) = half.reader.take() {
+      reader.notify();
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl AsyncRead for Duplex {}
+
+impl AsyncWrite for Duplex {
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    let mut half = self.write.lock().unwrap();
+    half.closed = true;
+    if let Some(reader) = half.reader.take() {
+      reader.notify();
+    }
+    Ok(Async::Ready(()))
+  }
+}
+
+impl Drop for Duplex {
+  fn drop(&mut self) {
+    let _ = self.shutdown();
+  }
+}
+
+// router lives as long as the spawned server
+fn serve(runtime: &mut Runtime, router: Router, config: Config) -> Duplex {
+  let router: &'static Router = Box::leak(Box::new(router));
+  let (client, server) = duplex();
+
+  let server = http2::serve_with_config(Plain::new(server), router, &config);
+  runtime.spawn(server.map_err(|_| ()));
+  client
+}
+
+// client with prior knowledge, connection is driven in the background
+fn connect(runtime: &mut Runtime, io: Duplex) -> SendRequest<Bytes> {
+  let (send, connection) = runtime.block_on(client::handshake(io)).unwrap();
+  runtime.spawn(connection.map_err(|_| ()));
+  send
+}
+
+fn get(path: &str) -> Request<()> {
+  Request::get(format!("http://localhost{}", path))
+    .body(())
+    .unwrap()
+}
+
+fn post(path: &str) -> Request<()> {
+  Request::post(format!("http://localhost{}", path))
+    .body(())
+    .unwrap()
+}
+
+fn body(runtime: &mut Runtime, response: Response<RecvStream>) -> Result<String, h2::Error> {
+  let body = runtime.block_on(response.into_body().concat2())?;
+  Ok(String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[test]
+fn prior_knowledge_connection_is_served_as_http2() {
+  let mut runtime = Runtime::new().unwrap();
+  let io = serve(&mut runtime, common::router(), Config::new());
+  let mut send = connect(&mut runtime, io);
+
+  let (response, _) = send.send_request(get("/hello"), true).unwrap();
+  let response = runtime.block_on(response).unwrap();
+
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.headers()["content-length"], "5");
+  assert!(response.headers().contains_key("date"));
+  assert!(!response.headers().contains_key("connection"));
+  assert_eq!(body(&mut runtime, response).unwrap(), "hello");
+}
+
+#[test]
+fn connection_without_preface_is_served_as_http1() {
+  let mut runtime = Runtime::new().unwrap();
+  let mut io = serve(&mut runtime, common::router(), Config::new());
+
+  io.write_all(b"GET /hello HTTP/1.1\r\nconnection: close\r\n\r\n")
+    .unwrap();
+  let output = runtime
+    .block_on(tokio::io::read_to_end(io, Vec::new()))
+    .unwrap()
+    .1;
+  let output = String::from_utf8(output).unwrap();
+
+  assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+  assert!(output.ends_with("\r\n\r\nhello"));
+}
+
+#[test]
+fn request_body_is_passed_to_on_data() {
+  let mut runtime = Runtime::new().unwrap();
+  let io = serve(&mut runtime, common::router(), Config::new());
+  let mut send = connect(&mut runtime, io);
+
+  let (response, mut stream) = send.send_request(post("/echo"), false).unwrap();
+  stream.send_data(Bytes::from("ab"), false).unwrap();
+  stream.send_data(Bytes::from("cde"), true).unwrap();
+  let response = runtime.block_on(response).unwrap();
+
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(body(&mut runtime, response).unwrap(), "abcde");
+}
+
+#[test]
+fn streams_are_answered_independently() {
+  let mut runtime = Runtime::new().unwrap();
+  let io = serve(&mut runtime, common::router(), Config::new());
+  let mut send = connect(&mut runtime, io);
+
+  let (echo, mut stream) = send.send_request(post("/echo"), false).unwrap();
+  let (hello, _) = send.send_request(get("/hello"), true).unwrap();
+
+  // second stream is answered while first one still waits for its body
+  let hello = runtime.block_on(hello).unwrap();
+  assert_eq!(body(&mut runtime, hello).unwrap(), "hello");
+
+  stream.send_data(Bytes::from("abc"), true).unwrap();
+  let echo = runtime.block_on(echo).unwrap();
+  assert_eq!(body(&mut runtime, echo).unwrap(), "abc");
+}
+
+#[test]
+fn missing_response_gets_500() {
+  let mut runtime = Runtime::new().unwrap();
+  let io = serve(&mut runtime, common::router(), Config::new());
+  let mut send = connect(&mut runtime, io);
+
+  let (response, _) = send.send_request(get("/silent"), true).unwrap();
+  let response = runtime.block_on(response).unwrap();
+
+  assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+  assert_eq!(body(&mut runtime, response).unwrap(), "");
+}
+
+#[test]
+fn body_over_limit_gets_413() {
+  let mut config = Config::new();
+  config.max_body_size = 4;
+
+  let mut runtime = Runtime::new().unwrap();
+  let io = serve(&mut runtime, common::router(), config);
+  let mut send = connect(&mut runtime, io);
+
+  let (response, mut stream) = send.send_request(post("/echo"), false).unwrap();
+  stream.send_data(Bytes::from("abcde"), true).unwrap();
+  let response = runtime.block_on(response).unwrap();
+
+  assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[test]
+fn body_over_limit_resets_started_response() {
+  let mut config = Config::new();
+  config.max_body_size = 4;
+
+  // response head is sent before the body is read
+  let mut router = Router::new();
+  router.add("POST", "/upload", |(mut req, res)| {
+    req.on_data(|(req, res)| Box::new(future::ok((req, res))));
+    Box::new(res.write_head().map(|res| (req, res)))
+  });
+
+  let mut runtime = Runtime::new().unwrap();
+  let io = serve(&mut runtime, router, config);
+  let mut send = connect(&mut runtime, io);
+
+  let (response, mut stream) = send.send_request(post("/upload"), false).unwrap();
+  let response = runtime.block_on(response).unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+
+  stream.send_data(Bytes::from("abcde"), false).unwrap();
+  assert!(body(&mut runtime, response).is_err());
+}
+
+#[test]
+fn stalled_body_gets_408() {
+  let mut config = Config::new();
+  config.body_timeout = Duration::from_millis(50);
+
+  let mut runtime = Runtime::new().unwrap();
+  let io = serve(&mut runtime, common::router(), config);
+  let mut send = connect(&mut runtime, io);
+
+  // body is never finished
+  let (response, mut stream) = send.send_request(post("/echo"), false).unwrap();
+  stream.send_data(Bytes::from("ab"), false).unwrap();
+  let response = runtime.block_on(response).unwrap();
+
+  assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+}
@@ -0,0 +1,286 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use peta::config::Config;
+
+fn timeouts() -> Config {
+  let mut config = Config::new();
+  config.keep_alive_timeout = Duration::from_millis(50);
+  config.header_timeout = Duration::from_millis(50);
+  config.body_timeout = Duration::from_millis(50);
+  config
+}
+
+#[test]
+fn idle_connection_is_closed_silently() {
+  let router = common::router();
+  let start = Instant::now();
+  let served = common::serve_open(&router, &timeouts(), &[]);
+
+  served.result.as_ref().unwrap();
+  assert!(start.elapsed() >= Duration::from_millis(50));
+  assert!(served.output.is_empty());
+  assert!(served.closed);
+}
+
+#[test]
+fn idle_keep_alive_connection_is_closed_after_response() {
+  let router = common::router();
+  let served = common::serve_open(&router, &timeouts(), &[b"GET /hello HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"]);
+  assert!(served.output.ends_with("\r\n\r\nhello"));
+  assert!(served.closed);
+}
+
+#[test]
+fn slow_request_head_gets_408() {
+  let router = common::router();
+  let served = common::serve_open(&router, &timeouts(), &[b"GET /hello HTTP/1.1\r\nhost: a"]);
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 408 Request Timeout"]);
+  assert!(served.output.contains("connection: close\r\n"));
+  assert!(served.closed);
+}
+
+#[test]
+fn head_timeout_replaces_keep_alive_once_head_started() {
+  let router = common::router();
+  let mut config = timeouts();
+  config.keep_alive_timeout = Duration::from_secs(10);
+
+  let start = Instant::now();
+  let served = common::serve_open(&router, &config, &[b"G", b"E", b"T"]);
+
+  assert!(start.elapsed() < Duration::from_secs(5));
+  assert_eq!(served.status_lines(), ["HTTP/1.1 408 Request Timeout"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn slow_request_body_gets_408() {
+  let router = common::router();
+  let served = common::serve_open(
+    &router,
+    &timeouts(),
+    &[b"POST /echo HTTP/1.1\r\ncontent-length: 10\r\n\r\nabc"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 408 Request Timeout"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn long_request_line_gets_414() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_request_line = 16;
+
+  let served = common::serve(
+    &router,
+    &config,
+    &[b"GET /hello?query=long-enough HTTP/1.1\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 414 URI Too Long"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn request_line_within_limit_is_served() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_request_line = 24;
+
+  let served = common::serve(&router, &config, &[b"GET /hello HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"]);
+}
+
+#[test]
+fn too_many_headers_get_431() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_headers = 2;
+
+  let served = common::serve(
+    &router,
+    &config,
+    &[b"GET /hello HTTP/1.1\r\na: 1\r\nb: 2\r\nc: 3\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(
+    served.status_lines(),
+    ["HTTP/1.1 431 Request Header Fields Too Large"]
+  );
+  assert!(served.closed);
+}
+
+#[test]
+fn large_complete_head_gets_431() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_header_bytes = 48;
+
+  let served = common::serve(
+    &router,
+    &config,
+    &[b"GET /hello HTTP/1.1\r\nx-padding: aaaaaaaaaaaaaaaaaaaaaaaa\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(
+    served.status_lines(),
+    ["HTTP/1.1 431 Request Header Fields Too Large"]
+  );
+}
+
+#[test]
+fn unfinished_head_over_limit_gets_431() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_header_bytes = 48;
+
+  // head never ends, it is rejected as soon as it grows past the limit
+  let served = common::serve_open(
+    &router,
+    &config,
+    &[b"GET /hello HTTP/1.1\r\nx-padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(
+    served.status_lines(),
+    ["HTTP/1.1 431 Request Header Fields Too Large"]
+  );
+  assert!(served.closed);
+}
+
+#[test]
+fn content_length_over_limit_gets_413() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_body_size = 4;
+
+  let served = common::serve(
+    &router,
+    &config,
+    &[b"POST /echo HTTP/1.1\r\ncontent-length: 5\r\n\r\nabcde"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 413 Payload Too Large"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn chunked_body_over_limit_gets_413() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_body_size = 4;
+
+  let served = common::serve(
+    &router,
+    &config,
+    &[
+      b"POST /echo HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n",
+    ],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 413 Payload Too Large"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn body_at_limit_is_served() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_body_size = 5;
+
+  let served = common::serve(
+    &router,
+    &config,
+    &[b"POST /echo HTTP/1.1\r\ncontent-length: 5\r\n\r\nabcde"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"]);
+  assert!(served.output.ends_with("\r\n\r\nabcde"));
+}
+
+#[test]
+fn malformed_request_line_gets_400() {
+  let router = common::router();
+  let served = common::serve(&router, &Config::new(), &[b"GET /hello HTTP/9\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 400 Bad Request"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn invalid_header_gets_400() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /hello HTTP/1.1\r\nbad header: 1\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 400 Bad Request"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn invalid_chunk_gets_400() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"POST /echo HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\nzz\r\nabc\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 400 Bad Request"]);
+  assert!(served.closed);
+}
+
+#[test]
+fn error_response_ends_pipelined_requests() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /hello HTTP/1.1\r\ncontent-length: x\r\n\r\nGET /hello HTTP/1.1\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 400 Bad Request"]);
+  assert!(!served.output.contains("hello"));
+}
+
+#[test]
+fn slow_head_after_keep_alive_response_gets_408() {
+  let router = common::router();
+  let served = common::serve_open(
+    &router,
+    &timeouts(),
+    &[b"GET /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nhost: a"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(
+    served.status_lines(),
+    ["HTTP/1.1 200 OK", "HTTP/1.1 408 Request Timeout"]
+  );
+  assert!(served.closed);
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use peta::config::Config;
+use peta::router::Router;
+use peta::*;
+
+#[test]
+fn requests_from_one_read_are_answered_in_order() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"POST /echo HTTP/1.1\r\ncontent-length: 3\r\n\r\nabc\
+        GET /hello HTTP/1.1\r\n\r\n\
+        POST /echo HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n2\r\nxy\r\n0\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 3);
+  assert!(served.output.contains("\r\n\r\nabcHTTP/1.1 200 OK"));
+  assert!(served.output.contains("\r\n\r\nhelloHTTP/1.1 200 OK"));
+  assert!(served.output.ends_with("\r\n\r\nxy"));
+  assert!(!served.output.contains("connection: close"));
+}
+
+#[test]
+fn request_after_content_length_body_is_kept() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[
+      b"POST /echo HTTP/1.1\r\ncontent-length: 5\r\n\r\nab",
+      b"cdeGET /hel",
+      b"lo HTTP/1.1\r\n\r\n",
+    ],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 2);
+  assert!(served.output.contains("\r\n\r\nabcdeHTTP/1.1 200 OK"));
+  assert!(served.output.ends_with("\r\n\r\nhello"));
+}
+
+#[test]
+fn request_after_chunked_body_is_kept() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[
+      b"POST /echo HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\nGET",
+      b" /hello HTTP/1.1\r\n\r\n",
+    ],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 2);
+  assert!(served.output.contains("\r\n\r\nabcHTTP/1.1 200 OK"));
+  assert!(served.output.ends_with("\r\n\r\nhello"));
+}
+
+#[test]
+fn long_pipeline_is_not_cut_short() {
+  let router = common::router();
+  let mut data = Vec::new();
+  for _ in 0..20 {
+    data.extend_from_slice(b"POST /echo HTTP/1.1\r\ncontent-length: 2\r\n\r\nok");
+    data.extend_from_slice(b"GET /hello HTTP/1.1\r\n\r\n");
+  }
+
+  let served = common::serve(&router, &Config::new(), &[&data]);
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 40);
+  assert!(!served.output.contains("connection: close"));
+  assert!(!served.closed);
+}
+
+#[test]
+fn read_ahead_is_limited() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_read_ahead = 64;
+
+  let mut data = Vec::new();
+  for _ in 0..10 {
+    data.extend_from_slice(b"GET /hello HTTP/1.1\r\n\r\n");
+  }
+  data.extend_from_slice(b"POST /echo HTTP/1.1\r\ncontent-length: 100\r\n\r\n");
+  data.extend_from_slice(&[b'a'; 100]);
+
+  let served = common::serve(&router, &config, &[&data]);
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 11);
+  assert!(served.output.ends_with(&"a".repeat(100)));
+  // rest of the body plus read ahead
+  assert!(served.largest_read <= 100 + 64);
+}
+
+#[test]
+fn read_ahead_stops_after_pipelined_limit() {
+  let router = common::router();
+  let mut config = Config::new();
+  config.max_pipelined = 2;
+
+  let served = common::serve(
+    &router,
+    &config,
+    &[
+      b"GET /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\nGET /hel",
+      b"lo HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\n",
+    ],
+  );
+
+  served.result.as_ref().unwrap();
+  // every request already sent is answered on the same connection
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"; 5]);
+  assert!(!served.output.contains("connection: close"));
+
+  // rest of the fourth head is read without read ahead, full reads resume once the
+  // buffer is drained
+  let tail = b"lo HTTP/1.1\r\n\r\n".len();
+  assert!(served.reads[1..=tail].iter().all(|&len| len == 1));
+  assert!(served.reads[tail + 1] > 1);
+}
+
+#[test]
+fn handler_without_response_closes_connection() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /silent HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\n"],
+  );
+
+  let err = served.result.as_ref().unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Handler finished without writing a response"
+  );
+  assert_eq!(
+    served.status_lines(),
+    ["HTTP/1.1 500 Internal Server Error"]
+  );
+  assert!(served.output.contains("connection: close"));
+  assert!(!served.output.contains("hello"));
+  assert!(served.closed);
+}
+
+#[test]
+fn extensions_are_kept_for_body_and_cleared_for_next_request() {
+  #[derive(Clone, Copy)]
+  struct Seq(usize);
+
+  let count = Arc::new(AtomicUsize::new(0));
+  let mut router = Router::new();
+  router.add("POST", "/ext", move |(mut req, res)| {
+    // value left from previous request on the connection would be replaced here
+    let seq = Seq(count.fetch_add(1, Ordering::SeqCst));
+    let previous = req.extensions_mut().insert(seq).map(|Seq(seq)| seq);
+
+    req.on_data(move |(req, res)| {
+      if !req.is_last() {
+        return Box::new(future::ok((req, res)));
+      }
+
+      let Seq(seq) = *req.extensions().get::<Seq>().unwrap();
+      let body = format!("seq={} previous={:?};", seq, previous);
+      Box::new(res.write(body).map(|res| (req, res)))
+    });
+
+    Box::new(future::ok((req, res)))
+  });
+
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"POST /ext HTTP/1.1\r\ncontent-length: 2\r\n\r\nab\
+        POST /ext HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n1\r\nc\r\n0\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 2);
+  assert!(served
+    .output
+    .contains("\r\n\r\nseq=0 previous=None;HTTP/1.1 200 OK"));
+  assert!(served.output.ends_with("\r\n\r\nseq=1 previous=None;"));
+}
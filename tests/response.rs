@@ -0,0 +1,138 @@
+mod common;
+
+use peta::config::Config;
+use peta::router::Router;
+use peta::*;
+
+#[test]
+fn streamed_body_is_sent_with_chunked_framing() {
+  let router = common::router();
+  let served = common::serve(&router, &Config::new(), &[b"GET /chunks HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert!(served
+    .output
+    .ends_with("transfer-encoding: chunked\r\n\r\n2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n"));
+  assert!(!served.output.contains("content-length"));
+}
+
+#[test]
+fn streamed_body_with_length_is_sent_as_is() {
+  let router = common::router();
+  let served = common::serve(&router, &Config::new(), &[b"GET /stream HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert!(served.output.ends_with("content-length: 5\r\n\r\nabcde"));
+  assert!(!served.output.contains("transfer-encoding"));
+}
+
+#[test]
+fn streamed_body_to_http10_client_is_delimited_by_close() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /chunks HTTP/1.0\r\nconnection: keep-alive\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert!(served.output.ends_with("connection: close\r\n\r\nabcde"));
+  assert!(served.closed);
+}
+
+// /close ends the connection with `Response::close`, /close-header with its own header
+fn closing_router() -> Router {
+  let mut router = common::router();
+
+  router.add("GET", "/close", |(req, mut res)| {
+    res.close();
+    Box::new(res.write("bye").map(|res| (req, res)))
+  });
+
+  router.add("GET", "/close-header", |(req, mut res)| {
+    res.header("connection", "close").unwrap();
+    Box::new(res.write("bye").map(|res| (req, res)))
+  });
+
+  router
+}
+
+#[test]
+fn http10_keep_alive_is_echoed_and_connection_kept() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /hello HTTP/1.0\r\nconnection: keep-alive\r\n\r\nGET /hello HTTP/1.0\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 2);
+
+  // second request does not ask for keep-alive and ends the connection
+  let (first, second) = served
+    .output
+    .split_at(served.output.rfind("HTTP/1.1").unwrap());
+  assert!(first.contains("connection: keep-alive\r\n"));
+  assert!(first.ends_with("\r\n\r\nhello"));
+  assert!(second.contains("connection: close\r\n"));
+  assert!(served.closed);
+}
+
+#[test]
+fn http10_without_keep_alive_is_closed() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /hello HTTP/1.0\r\n\r\nGET /hello HTTP/1.0\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"]);
+  assert!(served.output.contains("connection: close\r\n"));
+  assert!(served.closed);
+}
+
+#[test]
+fn http11_connection_is_kept_by_default() {
+  let router = common::router();
+  let served = common::serve(&router, &Config::new(), &[b"GET /hello HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert!(!served.output.contains("connection:"));
+  assert!(!served.closed);
+}
+
+#[test]
+fn http11_connection_close_ends_connection() {
+  let router = common::router();
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /hello HTTP/1.1\r\nconnection: Close\r\n\r\nGET /hello HTTP/1.1\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"]);
+  assert!(served.output.contains("connection: close\r\n"));
+  assert!(served.output.ends_with("\r\n\r\nhello"));
+  assert!(served.closed);
+}
+
+#[test]
+fn handler_can_close_connection() {
+  let router = closing_router();
+
+  for path in &["/close", "/close-header"] {
+    let request = format!("GET {} HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\n\r\n", path);
+    let served = common::serve(&router, &Config::new(), &[request.as_bytes()]);
+
+    served.result.as_ref().unwrap();
+    assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"], "{}", path);
+    // header is written once even when handler set it itself
+    assert_eq!(served.output.matches("connection: close\r\n").count(), 1);
+    assert!(served.output.ends_with("\r\n\r\nbye"));
+    assert!(served.closed);
+  }
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use peta::config::Config;
+use peta::router::Router;
+use peta::sse::Event;
+use peta::*;
+
+// one event with id following the client's last one, stream ends after `idle`
+fn router(idle: Duration) -> Router {
+  let mut router = Router::new();
+  router.add("GET", "/events", move |(req, res)| {
+    let id = match req.last_event_id() {
+      Some(id) => format!("{}+1", id),
+      None => "none".to_string(),
+    };
+
+    let end = tokio::timer::Delay::new(Instant::now() + idle)
+      .map_err(std::io::Error::other)
+      .into_stream()
+      .filter_map(|_| None);
+    let events = stream::once(Ok(Event::new("hello").id(&id))).chain(end);
+
+    let heartbeat = Duration::from_millis(20);
+    Box::new(res.write_sse(events, heartbeat).map(|res| (req, res)))
+  });
+  router
+}
+
+#[test]
+fn last_event_id_is_read_from_request() {
+  let router = router(Duration::from_millis(0));
+  let served = common::serve(
+    &router,
+    &Config::new(),
+    &[b"GET /events HTTP/1.1\r\nLast-Event-ID: 41\r\n\r\nGET /events HTTP/1.1\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"; 2]);
+  assert!(served.output.contains("id: 41+1\ndata: hello\n\n"));
+  // header belongs to its request only
+  assert!(served.output.contains("id: none\ndata: hello\n\n"));
+}
+
+#[test]
+fn heartbeat_is_sent_while_stream_is_idle() {
+  let mut config = Config::new();
+  // long lived response is not cut by the keep-alive timer
+  config.keep_alive_timeout = Duration::from_millis(10);
+
+  let router = router(Duration::from_millis(100));
+  let served = common::serve(&router, &config, &[b"GET /events HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert!(served
+    .output
+    .contains("content-type: text/event-stream\r\n"));
+  assert!(served.output.contains("cache-control: no-cache\r\n"));
+  // every heartbeat comment is a chunk of its own, stream ends with the last chunk
+  assert!(served.output.contains("\r\n3\r\n:\n\n\r\n"));
+  assert!(served.output.ends_with("\r\n0\r\n\r\n"));
+
+  let event = served.output.find("data: hello").unwrap();
+  let heartbeat = served.output.find(":\n\n").unwrap();
+  assert!(event < heartbeat);
+}
@@ -0,0 +1,347 @@
+#![cfg(feature = "tls")]
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bytes::Buf;
+use rustls::internal::pemfile;
+use rustls::{Certificate, ClientConfig, ClientSession, Session, StreamOwned};
+use tokio::net::TcpListener;
+
+use peta::reader::Reader;
+use peta::router::Router;
+use peta::tls;
+use peta::transport::{AltName, ClientCert, Transport};
+use peta::*;
+
+// server socket counting writes which reach it
+struct Counted {
+  io: tokio::net::TcpStream,
+  writes: Arc<AtomicUsize>,
+  // writes done before the socket was shut down
+  writes_at_close: Arc<AtomicUsize>,
+}
+
+impl Read for Counted {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.io.read(buf)
+  }
+}
+
+impl Write for Counted {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.writes.fetch_add(1, Ordering::SeqCst);
+    self.io.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.io.flush()
+  }
+}
+
+impl AsyncRead for Counted {}
+
+impl AsyncWrite for Counted {
+  fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+    self.writes.fetch_add(1, Ordering::SeqCst);
+    self.io.write_buf(buf)
+  }
+
+  fn shutdown(&mut self) -> Poll<(), io::Error> {
+    let writes = self.writes.load(Ordering::SeqCst);
+    self.writes_at_close.store(writes, Ordering::SeqCst);
+    AsyncWrite::shutdown(&mut self.io)
+  }
+}
+
+impl Transport for Counted {
+  fn peer_addr(&self) -> Option<SocketAddr> {
+    self.io.peer_addr().ok()
+  }
+}
+
+// tls details seen by the handler
+#[derive(Default)]
+struct Seen {
+  sni: Option<String>,
+  version: Option<String>,
+  client_cert: Option<ClientCert>,
+}
+
+fn router(seen: Arc<Mutex<Seen>>) -> Router {
+  let mut router = Router::new();
+  router.add("GET", "/tls", move |(req, res)| {
+    *seen.lock().unwrap() = Seen {
+      sni: req.sni().map(String::from),
+      version: req.tls_version().map(String::from),
+      client_cert: req.client_cert().cloned(),
+    };
+    Box::new(res.write("ok").map(|res| (req, res)))
+  });
+  router
+}
+
+fn certs(path: &str) -> Vec<Certificate> {
+  pemfile::certs(&mut io::BufReader::new(std::fs::File::open(path).unwrap())).unwrap()
+}
+
+struct Response {
+  // None when handshake failed
+  body: Option<String>,
+  // end entity certificate presented by the server
+  server_cert: Option<Certificate>,
+}
+
+// client trusting tests/certs/ca.pem, `identity` is the certificate it authenticates with
+fn client(
+  addr: SocketAddr,
+  hostname: &'static str,
+  identity: Option<&'static str>,
+) -> thread::JoinHandle<Response> {
+  thread::spawn(move || {
+    let mut config = ClientConfig::new();
+    let mut ca = io::BufReader::new(&include_bytes!("certs/ca.pem")[..]);
+    config.root_store.add_pem_file(&mut ca).unwrap();
+
+    if let Some(name) = identity {
+      let key = pemfile::pkcs8_private_keys(&mut io::BufReader::new(
+        std::fs::File::open(format!("tests/certs/{}.key", name)).unwrap(),
+      ))
+      .unwrap()
+      .remove(0);
+      config.set_single_client_cert(certs(&format!("tests/certs/{}.pem", name)), key);
+    }
+
+    let name = webpki::DNSNameRef::try_from_ascii_str(hostname).unwrap();
+    let session = ClientSession::new(&Arc::new(config), name);
+    let mut stream = StreamOwned::new(session, std::net::TcpStream::connect(addr).unwrap());
+
+    let request = format!(
+      "GET /tls HTTP/1.1\r\nhost: {}\r\nconnection: close\r\n\r\n",
+      hostname
+    );
+    let mut body = Vec::new();
+    let result = stream
+      .write_all(request.as_bytes())
+      .and_then(|_| stream.read_to_end(&mut body));
+
+    let body = match result {
+      Ok(_) => Some(body),
+      // close_notify from the server
+      Err(ref e) if e.kind() == ErrorKind::ConnectionAborted => Some(body),
+      Err(_) => None,
+    };
+
+    Response {
+      body: body.map(|body| String::from_utf8(body).unwrap()),
+      server_cert: stream
+        .sess
+        .get_peer_certificates()
+        .and_then(|certs| certs.into_iter().next()),
+    }
+  })
+}
+
+// accept one connection and serve it to the end
+fn serve_one(
+  listener: TcpListener,
+  acceptor: tls::Acceptor,
+  router: &Router,
+) -> Result<(), io::Error> {
+  let server = listener
+    .incoming()
+    .into_future()
+    .map_err(|(e, _)| e)
+    .and_then(move |(socket, _)| Reader::accept(acceptor.accept(socket.unwrap()), router))
+    .map(|_| ());
+
+  let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+  runtime.block_on(server)
+}
+
+fn listen() -> (TcpListener, SocketAddr) {
+  let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+  let addr = listener.local_addr().unwrap();
+  (listener, addr)
+}
+
+// certificates in tests/certs are signed by ca.pem
+#[test]
+fn request_round_trip_over_tls() {
+  let acceptor = tls::Builder::new()
+    .cert("tests/certs/localhost.pem", "tests/certs/localhost.key")
+    .unwrap()
+    .build()
+    .unwrap();
+
+  let seen = Arc::new(Mutex::new(Seen::default()));
+  let router = router(seen.clone());
+
+  let (listener, addr) = listen();
+  let client = client(addr, "localhost", None);
+
+  let router = &router;
+  let writes = Arc::new(AtomicUsize::new(0));
+  let writes_at_close = Arc::new(AtomicUsize::new(0));
+  let counted = (writes.clone(), writes_at_close.clone());
+
+  let server = listener
+    .incoming()
+    .into_future()
+    .map_err(|(e, _)| e)
+    .and_then(move |(socket, _)| {
+      let socket = Counted {
+        io: socket.unwrap(),
+        writes: counted.0,
+        writes_at_close: counted.1,
+      };
+      // only writes after the handshake are counted
+      let handshake = acceptor.accept(socket).map(move |stream| {
+        writes.store(0, Ordering::SeqCst);
+        stream
+      });
+      Reader::accept(handshake, router)
+    });
+
+  let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+  runtime.block_on(server).unwrap();
+
+  let body = client.join().unwrap().body.unwrap();
+  assert!(body.starts_with("HTTP/1.1 200 OK\r\n"));
+  assert!(body.ends_with("\r\n\r\nok"));
+  // head and body leave in one write, followed by close_notify
+  assert_eq!(writes_at_close.load(Ordering::SeqCst), 2);
+
+  let seen = seen.lock().unwrap();
+  assert_eq!(seen.sni.as_deref(), Some("localhost"));
+  assert_eq!(seen.version.as_deref(), Some("TLSv1.3"));
+  assert!(seen.client_cert.is_none());
+}
+
+#[test]
+fn certificate_is_selected_by_sni() {
+  let localhost = certs("tests/certs/localhost.pem").remove(0);
+  let other = certs("tests/certs/other.pem").remove(0);
+
+  for (hostname, expected) in &[("other.test", &other), ("localhost", &localhost)] {
+    let acceptor = tls::Builder::new()
+      .cert("tests/certs/localhost.pem", "tests/certs/localhost.key")
+      .unwrap()
+      .sni_cert(
+        "other.test",
+        "tests/certs/other.pem",
+        "tests/certs/other.key",
+      )
+      .unwrap()
+      .build()
+      .unwrap();
+
+    let seen = Arc::new(Mutex::new(Seen::default()));
+    let router = router(seen.clone());
+
+    let (listener, addr) = listen();
+    let client = client(addr, hostname, None);
+    serve_one(listener, acceptor, &router).unwrap();
+
+    let response = client.join().unwrap();
+    assert!(response.body.unwrap().ends_with("\r\n\r\nok"));
+    assert_eq!(response.server_cert.as_ref(), Some(*expected));
+    assert_eq!(seen.lock().unwrap().sni.as_deref(), Some(*hostname));
+  }
+}
+
+#[test]
+fn sni_certificate_has_to_match_its_hostname() {
+  let err = tls::Builder::new()
+    .sni_cert(
+      "other.test",
+      "tests/certs/localhost.pem",
+      "tests/certs/localhost.key",
+    )
+    .err()
+    .unwrap();
+  assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn client_certificate_is_exposed_to_handler() {
+  let acceptor = tls::Builder::new()
+    .cert("tests/certs/localhost.pem", "tests/certs/localhost.key")
+    .unwrap()
+    .client_ca("tests/certs/ca.pem", true)
+    .unwrap()
+    .build()
+    .unwrap();
+
+  let seen = Arc::new(Mutex::new(Seen::default()));
+  let router = router(seen.clone());
+
+  let (listener, addr) = listen();
+  let client = client(addr, "localhost", Some("client"));
+  serve_one(listener, acceptor, &router).unwrap();
+
+  assert!(client.join().unwrap().body.unwrap().ends_with("\r\n\r\nok"));
+
+  let seen = seen.lock().unwrap();
+  let cert = seen.client_cert.as_ref().unwrap();
+  assert_eq!(cert.subject, "CN=client,O=Example");
+  assert_eq!(
+    cert.alt_names,
+    [
+      AltName::Dns("client.example".to_string()),
+      AltName::Email("client@example.com".to_string()),
+      AltName::Uri("spiffe://example/client".to_string()),
+      AltName::Ip("127.0.0.1".parse().unwrap()),
+    ]
+  );
+  assert_eq!(cert.der, certs("tests/certs/client.pem")[0].0);
+}
+
+#[test]
+fn required_client_certificate_is_enforced() {
+  let acceptor = tls::Builder::new()
+    .cert("tests/certs/localhost.pem", "tests/certs/localhost.key")
+    .unwrap()
+    .client_ca("tests/certs/ca.pem", true)
+    .unwrap()
+    .build()
+    .unwrap();
+
+  let seen = Arc::new(Mutex::new(Seen::default()));
+  let router = router(seen.clone());
+
+  let (listener, addr) = listen();
+  let client = client(addr, "localhost", None);
+  let err = serve_one(listener, acceptor, &router).unwrap_err();
+
+  assert_eq!(err.kind(), ErrorKind::InvalidData);
+  assert!(client.join().unwrap().body.is_none());
+  // handler is never reached
+  assert!(seen.lock().unwrap().version.is_none());
+}
+
+#[test]
+fn optional_client_certificate_may_be_omitted() {
+  let acceptor = tls::Builder::new()
+    .cert("tests/certs/localhost.pem", "tests/certs/localhost.key")
+    .unwrap()
+    .client_ca("tests/certs/ca.pem", false)
+    .unwrap()
+    .build()
+    .unwrap();
+
+  let seen = Arc::new(Mutex::new(Seen::default()));
+  let router = router(seen.clone());
+
+  let (listener, addr) = listen();
+  let client = client(addr, "localhost", None);
+  serve_one(listener, acceptor, &router).unwrap();
+
+  assert!(client.join().unwrap().body.unwrap().ends_with("\r\n\r\nok"));
+  let seen = seen.lock().unwrap();
+  assert_eq!(seen.version.as_deref(), Some("TLSv1.3"));
+  assert!(seen.client_cert.is_none());
+}
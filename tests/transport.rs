@@ -0,0 +1,125 @@
+mod common;
+
+use peta::config::Config;
+use peta::proxy;
+use peta::transport::Rewind;
+use peta::BytesMut;
+
+#[test]
+fn reader_runs_over_in_memory_stream() {
+  let router = common::router();
+  let served = common::serve(&router, &Config::new(), &[b"GET /hello HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 200 OK"]);
+  assert!(served.output.ends_with("content-length: 5\r\n\r\nhello"));
+}
+
+#[test]
+fn plain_stream_has_no_addresses() {
+  let router = common::router();
+  let served = common::serve(&router, &Config::new(), &[b"GET /addr HTTP/1.1\r\n\r\n"]);
+
+  assert!(served.output.ends_with("\r\n\r\nNone None"));
+}
+
+#[test]
+fn buffered_response_is_flushed_before_close() {
+  let router = common::router();
+  let served = common::serve_buffered(
+    &router,
+    &Config::new(),
+    &[b"GET /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nconnection: close\r\n\r\n"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines().len(), 2);
+  assert!(served
+    .output
+    .ends_with("connection: close\r\ncontent-length: 5\r\n\r\nhello"));
+  assert!(served.closed);
+}
+
+#[test]
+fn head_and_body_are_sent_in_one_vectored_write() {
+  let router = common::router();
+  let served = common::serve(&router, &Config::new(), &[b"GET /hello HTTP/1.1\r\n\r\n"]);
+
+  served.result.as_ref().unwrap();
+  assert!(served.output.ends_with("\r\n\r\nhello"));
+  assert_eq!(served.write_bufs, 1);
+}
+
+#[test]
+fn rewind_keeps_vectored_write() {
+  let router = common::router();
+  let served = common::serve_wrapped(
+    &router,
+    &Config::new(),
+    &[b"GET /hello HTTP/1.1\r\n\r\n"],
+    |pipe| Ok(Rewind::new(pipe, BytesMut::new())),
+  );
+
+  served.result.as_ref().unwrap();
+  assert!(served.output.ends_with("\r\n\r\nhello"));
+  assert_eq!(served.write_bufs, 1);
+}
+
+#[test]
+fn proxy_stream_keeps_vectored_write() {
+  let router = common::router();
+  let served = common::serve_wrapped(
+    &router,
+    &Config::new(),
+    &[b"PROXY TCP4 10.0.0.1 10.0.0.2 4000 80\r\nGET /addr HTTP/1.1\r\n\r\n"],
+    proxy::accept,
+  );
+
+  served.result.as_ref().unwrap();
+  assert!(served
+    .output
+    .ends_with("\r\n\r\nSome(10.0.0.1:4000) Some(10.0.0.2:80)"));
+  assert_eq!(served.write_bufs, 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_exposes_peer_credentials() {
+  use std::sync::{Arc, Mutex};
+
+  use peta::reader::Reader;
+  use peta::router::Router;
+  use peta::transport::PeerCred;
+  use peta::*;
+  use tokio::net::UnixStream;
+
+  let seen: Arc<Mutex<Option<PeerCred>>> = Arc::default();
+  let mut router = Router::new();
+  let cred = seen.clone();
+  router.add("GET", "/cred", move |(req, res)| {
+    *cred.lock().unwrap() = req.peer_cred();
+    Box::new(res.write("ok").map(|res| (req, res)))
+  });
+
+  // both ends belong to this process
+  let (server, client) = UnixStream::pair().unwrap();
+  let request = b"GET /cred HTTP/1.1\r\nconnection: close\r\n\r\n";
+  let client = tokio::io::write_all(client, &request[..])
+    .and_then(|(client, _)| tokio::io::read_to_end(client, Vec::new()))
+    .map(|(_, output)| String::from_utf8(output).unwrap());
+
+  // spawned task is dropped once finished, which closes the socket for the client
+  let router: &'static Router = Box::leak(Box::new(router));
+  let server = Reader::new(server, router).map(|_| ()).map_err(|_| ());
+  let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+  runtime.spawn(server);
+  let output = runtime.block_on(client).unwrap();
+  assert!(output.ends_with("\r\n\r\nok"));
+
+  let cred = seen.lock().unwrap().unwrap();
+  assert_eq!(cred.uid, unsafe { libc::getuid() });
+  assert_eq!(cred.gid, unsafe { libc::getgid() });
+  if cfg!(any(target_os = "linux", target_os = "android")) {
+    assert_eq!(cred.pid, Some(std::process::id() as i32));
+  }
+}
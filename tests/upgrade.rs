@@ -0,0 +1,39 @@
+mod common;
+
+use peta::config::Config;
+use peta::router::Router;
+use peta::*;
+
+// echoes data client sent right after the request head and leaves without closing
+fn router() -> Router {
+  let mut router = Router::new();
+
+  router.add("GET", "/echo", |(mut req, res)| {
+    let upgraded = req.on_upgrade().and_then(|mut upgraded| {
+      let buffer = upgraded.read.take_prefix();
+      tokio::io::write_all(upgraded.write, buffer).and_then(|(write, _)| tokio::io::flush(write))
+    });
+    tokio::spawn(upgraded.map(|_| ()).map_err(|e| panic!("{}", e)));
+
+    match res.upgrade("echo") {
+      Ok(fut) => Box::new(fut.map(|res| (req, res))),
+      Err(e) => Box::new(future::err(e)),
+    }
+  });
+
+  router
+}
+
+#[test]
+fn upgraded_write_is_flushed() {
+  let router = router();
+  let served = common::serve_buffered(
+    &router,
+    &Config::new(),
+    &[b"GET /echo HTTP/1.1\r\nconnection: upgrade\r\nupgrade: echo\r\n\r\nping"],
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 101 Switching Protocols"]);
+  assert!(served.output.ends_with("connection: upgrade\r\n\r\nping"));
+}
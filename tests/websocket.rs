@@ -0,0 +1,69 @@
+#![cfg(feature = "websocket")]
+
+mod common;
+
+use peta::config::Config;
+use peta::router::Router;
+use peta::websocket;
+use peta::*;
+
+fn router() -> Router {
+  let mut router = Router::new();
+  router.websocket("/ws", websocket::Config::new(), |_| future::ok(()));
+  router
+}
+
+fn handshake(headers: &str) -> common::Served {
+  let request = format!("GET /ws HTTP/1.1\r\nhost: localhost\r\n{}\r\n", headers);
+  common::serve(&router(), &Config::new(), &[request.as_bytes()])
+}
+
+#[test]
+fn handshake_is_accepted() {
+  // sample handshake from RFC 6455
+  let served = handshake(
+    "upgrade: websocket\r\nconnection: Upgrade\r\n\
+     sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\nsec-websocket-version: 13\r\n",
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 101 Switching Protocols"]);
+  assert!(served.output.contains("upgrade: websocket\r\n"));
+  assert!(served
+    .output
+    .contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+}
+
+#[test]
+fn request_without_upgrade_gets_400() {
+  let served = handshake(
+    "connection: keep-alive\r\n\
+     sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\nsec-websocket-version: 13\r\n",
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 400 Bad Request"]);
+}
+
+#[test]
+fn invalid_key_gets_400() {
+  let served = handshake(
+    "upgrade: websocket\r\nconnection: Upgrade\r\n\
+     sec-websocket-key: short\r\nsec-websocket-version: 13\r\n",
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 400 Bad Request"]);
+}
+
+#[test]
+fn unsupported_version_gets_426() {
+  let served = handshake(
+    "upgrade: websocket\r\nconnection: Upgrade\r\n\
+     sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\nsec-websocket-version: 8\r\n",
+  );
+
+  served.result.as_ref().unwrap();
+  assert_eq!(served.status_lines(), ["HTTP/1.1 426 Upgrade Required"]);
+  assert!(served.output.contains("sec-websocket-version: 13\r\n"));
+}